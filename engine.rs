@@ -0,0 +1,1230 @@
+// The nod-99 game engine: cards, rulesets, players, the deck, game logs and the game and tournament runners
+// nod-99 builds it as a module. It also builds on its own as a library, with rand as its only dependency, so a team
+// can implement Player in its own crate and play it with run_game and the tournaments. Add it to a manifest with
+//
+//   [lib]
+//   name = "nod99"
+//   path = "engine.rs"
+use std::{io, vec};
+use std::io::Write;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/////////////////////
+/// Card Section ///
+///////////////////
+// Declare Card enum
+#[derive(PartialEq, Eq, Hash)]
+pub enum Card {
+    Ace,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Ten,
+    Jack,
+    Queen,
+    King,
+    OneEye
+}
+
+impl Card {
+    pub fn num_form (&self) -> i32 {
+        match self {
+            Card::Ace => 1,
+            Card::Two => 2,
+            Card::Three => 3,
+            Card::Four => 4,
+            Card::Five => 5,
+            Card::Six => 6,
+            Card::Seven => 7,
+            Card::Eight => 8,
+            Card::Nine => 9,
+            Card::Ten => 10,
+            Card::Jack => 11,
+            Card::Queen => 12,
+            Card::King => 13,
+            Card::OneEye => 0
+        }
+    }
+
+    // Inverse of num_form
+    pub fn from_num_form(number: usize) -> Card {
+        match number {
+            1 => Card::Ace,
+            2 => Card::Two,
+            3 => Card::Three,
+            4 => Card::Four,
+            5 => Card::Five,
+            6 => Card::Six,
+            7 => Card::Seven,
+            8 => Card::Eight,
+            9 => Card::Nine,
+            10 => Card::Ten,
+            11 => Card::Jack,
+            12 => Card::Queen,
+            13 => Card::King,
+            _ => Card::OneEye
+        }
+    }
+
+    // Inverse of Display
+    pub fn from_name(name: &str) -> Option<Card> {
+        match name {
+            "Ace" => Some(Card::Ace),
+            "Two" => Some(Card::Two),
+            "Three" => Some(Card::Three),
+            "Four" => Some(Card::Four),
+            "Five" => Some(Card::Five),
+            "Six" => Some(Card::Six),
+            "Seven" => Some(Card::Seven),
+            "Eight" => Some(Card::Eight),
+            "Nine" => Some(Card::Nine),
+            "Ten" => Some(Card::Ten),
+            "Jack" => Some(Card::Jack),
+            "Queen" => Some(Card::Queen),
+            "King" => Some(Card::King),
+            "OneEye" => Some(Card::OneEye),
+            _ => None
+        }
+    }
+}
+
+// Implement a string format for each card
+impl std::fmt::Display for Card {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Card::Ace => write!(f, "Ace"),
+            Card::Two => write!(f, "Two"),
+            Card::Three => write!(f, "Three"),
+            Card::Four => write!(f, "Four"),
+            Card::Five => write!(f, "Five"),
+            Card::Six => write!(f, "Six"),
+            Card::Seven => write!(f, "Seven"),
+            Card::Eight => write!(f, "Eight"),
+            Card::Nine => write!(f, "Nine"),
+            Card::Ten => write!(f, "Ten"),
+            Card::Jack => write!(f, "Jack"),
+            Card::Queen => write!(f, "Queen"),
+            Card::King => write!(f, "King"),
+            Card::OneEye => write!(f, "OneEye")
+        }
+    }
+}
+
+// Implement clone for Card
+impl Clone for Card {
+    fn clone(&self) -> Card{
+        match self {
+            Card::Ace => Card::Ace,
+            Card::Two => Card::Two,
+            Card::Three => Card::Three,
+            Card::Four => Card::Four,
+            Card::Five => Card::Five,
+            Card::Six => Card::Six,
+            Card::Seven => Card::Seven,
+            Card::Eight => Card::Eight,
+            Card::Nine => Card::Nine,
+            Card::Ten => Card::Ten,
+            Card::Jack => Card::Jack,
+            Card::Queen => Card::Queen,
+            Card::King => Card::King,
+            Card::OneEye => Card::OneEye
+        }
+    }
+}
+
+
+
+/////////////////////
+/// Rules Section ///
+///////////////////
+// What a card does to the running total
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TotalEffect {
+    Add(i64),
+    SetTo(i64),
+    Either(i64, i64)
+}
+
+// What a card does to the order of play
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TurnEffect {
+    Pass,
+    Reverse
+}
+
+// A house-rule set, both tables are indexed by Card::num_form
+#[derive(Clone)]
+pub struct Ruleset {
+    pub name: &'static str,
+    pub total: [TotalEffect; 14],
+    pub turn: [TurnEffect; 14],
+    pub reshuffle: ReshufflePolicy
+}
+
+impl Ruleset {
+    // The rules the bots were originally trained under
+    pub fn standard() -> Ruleset {
+        Ruleset {
+            name: "standard",
+            total: [
+                TotalEffect::SetTo(99), // OneEye
+                TotalEffect::Add(1),
+                TotalEffect::Add(2),
+                TotalEffect::Add(3),
+                TotalEffect::Add(4),
+                TotalEffect::Add(5),
+                TotalEffect::Add(6),
+                TotalEffect::Add(7),
+                TotalEffect::Add(0),
+                TotalEffect::Add(0),
+                TotalEffect::Add(-10),
+                TotalEffect::Add(10),
+                TotalEffect::Add(10),
+                TotalEffect::Add(10)
+            ],
+            turn: {
+                let mut turn = [TurnEffect::Pass; 14];
+                turn[Card::Eight.num_form() as usize] = TurnEffect::Reverse;
+                turn
+            },
+            reshuffle: ReshufflePolicy::WholeDiscard
+        }
+    }
+
+    // Four reverses and holds, Eight counts as eight
+    fn four_reverses() -> Ruleset {
+        let mut rules = Ruleset::standard();
+        rules.name = "four-reverses";
+        rules.total[Card::Four.num_form() as usize] = TotalEffect::Add(0);
+        rules.turn[Card::Four.num_form() as usize] = TurnEffect::Reverse;
+        rules.total[Card::Eight.num_form() as usize] = TotalEffect::Add(8);
+        rules.turn[Card::Eight.num_form() as usize] = TurnEffect::Pass;
+        rules
+    }
+
+    // King jumps straight to 99 alongside the OneEye
+    fn king_ninety_nine() -> Ruleset {
+        let mut rules = Ruleset::standard();
+        rules.name = "king-99";
+        rules.total[Card::King.num_form() as usize] = TotalEffect::SetTo(99);
+        rules
+    }
+
+    // Ten may be played as plus or minus ten
+    fn ten_either_way() -> Ruleset {
+        let mut rules = Ruleset::standard();
+        rules.name = "ten-either-way";
+        rules.total[Card::Ten.num_form() as usize] = TotalEffect::Either(10, -10);
+        rules
+    }
+
+    // Ace may be played as one or eleven, Ten as plus or minus ten
+    fn ace_one_or_eleven() -> Ruleset {
+        let mut rules = Ruleset::ten_either_way();
+        rules.name = "ace-one-or-eleven";
+        rules.total[Card::Ace.num_form() as usize] = TotalEffect::Either(11, 1);
+        rules
+    }
+
+    // Standard cards, but the ten most recent discards stay face up when the deck is reshuffled
+    // With them out of the deck a draw can come up empty and leave a hand short
+    fn keep_last_ten() -> Ruleset {
+        let mut rules = Ruleset::standard();
+        rules.name = "keep-last-10";
+        rules.reshuffle = ReshufflePolicy::KeepLast(10);
+        rules
+    }
+
+    // Look up a ruleset by the name it reports
+    pub fn by_name(name: &str) -> Option<Ruleset> {
+        match name {
+            "standard" => Some(Ruleset::standard()),
+            "keep-last-10" => Some(Ruleset::keep_last_ten()),
+            "four-reverses" => Some(Ruleset::four_reverses()),
+            "king-99" => Some(Ruleset::king_ninety_nine()),
+            "ten-either-way" => Some(Ruleset::ten_either_way()),
+            "ace-one-or-eleven" => Some(Ruleset::ace_one_or_eleven()),
+            _ => None
+        }
+    }
+
+    // The (high, low) amounts a player may declare for the card, None if it only has one value
+    pub fn choices(&self, card: &Card) -> Option<(i64, i64)> {
+        match self.total[card.num_form() as usize] {
+            TotalEffect::Either(a, b) => Some(if a > b { (a, b) } else { (b, a) }),
+            _ => None
+        }
+    }
+
+    // The value taken when nothing is declared: the larger amount that stays at or under 99
+    pub fn default_value(&self, card: &Card, number: i64) -> Option<i64> {
+        let (high, low) = self.choices(card)?;
+        if number + high <= 99 {
+            Some(high)
+        } else {
+            Some(low)
+        }
+    }
+
+    // The total after the card is played on number with its default value
+    pub fn new_number(&self, card: &Card, number: i64) -> i64 {
+        match self.total[card.num_form() as usize] {
+            TotalEffect::Add(x) => number + x,
+            TotalEffect::SetTo(x) => x,
+            TotalEffect::Either(_, _) => number + self.default_value(card, number).unwrap_or(0)
+        }
+    }
+
+    // The total after a play, honouring its declared value when that value is legal for the card
+    pub fn play_number(&self, play: &Play, number: i64) -> i64 {
+        match (self.choices(&play.card), play.declared) {
+            (Some((high, low)), Some(x)) if x == high || x == low => number + x,
+            _ => self.new_number(&play.card, number)
+        }
+    }
+
+    pub fn reverses(&self, card: &Card) -> bool {
+        self.turn[card.num_form() as usize] == TurnEffect::Reverse
+    }
+
+    // Whether any card may be declared as one of two values
+    pub fn has_choices(&self) -> bool {
+        self.total.iter().any(|effect| matches!(effect, TotalEffect::Either(_, _)))
+    }
+
+    // Most seats the deck deals full hands to with cards to spare, not counting the discards a reshuffle
+    // keeps out, so a draw never comes up empty
+    pub fn max_seats(&self) -> usize {
+        let kept: usize = match self.reshuffle {
+            ReshufflePolicy::WholeDiscard => 0,
+            ReshufflePolicy::KeepLast(n) => n
+        };
+        (build_deck().len().saturating_sub(kept) / 3).saturating_sub(1)
+    }
+}
+
+
+//////////////////////
+// PLAYERS SECTION //
+////////////////////
+
+// A card leaving a hand, with the value declared for cards that have more than one
+#[derive(Clone)]
+pub struct Play {
+    pub card: Card,
+    pub declared: Option<i64>
+}
+
+// What a player can see on their turn, borrowed from run_game
+pub struct GameState<'a> {
+    pub total: i64,
+    pub direction: i64,
+    pub seat: usize,
+    pub num_players: usize,
+    // Discards since the last reshuffle, indexed by Card::num_form
+    pub counts: &'a [usize; 14],
+    // Every play this game, oldest first
+    pub history: &'a Vec<Play>,
+    pub deck_size: usize,
+    pub rules: &'a Ruleset
+}
+
+// Anything that can sit at the table, implement this to bring a new strategy to run_game and the tournaments
+// Only hand, loss and turn access are required, the rest build on them
+// A player has to be Send and Clone so the tournaments can play copies of it on other threads, deriving Clone is enough
+pub trait Player: PlayerClone + Send {
+    fn hand(&self) -> &Vec<Card>;
+    fn hand_mut(&mut self) -> &mut Vec<Card>;
+    fn loss_count(&self) -> u32;
+    fn add_losses(&mut self, count: u32);
+    fn take_turn(&mut self, state: &GameState) -> Play;
+
+    fn take_card(&mut self, card: Card) {
+        self.hand_mut().push(card);
+    }
+
+    fn clear_hand(&mut self) {
+        self.hand_mut().clear();
+    }
+
+    fn lose(&mut self) {
+        self.add_losses(1);
+    }
+
+    // Re-draw any random parameters, most players have none
+    fn rand(&mut self, _rng: &mut StdRng) {}
+}
+
+// Independent boxed copy, used to play matchups in parallel
+pub trait PlayerClone {
+    fn box_clone(&self) -> Box<dyn Player>;
+}
+
+impl<T: Player + Clone + 'static> PlayerClone for T {
+    fn box_clone(&self) -> Box<dyn Player> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Player> {
+    fn clone(&self) -> Box<dyn Player> {
+        self.box_clone()
+    }
+}
+
+// Define the real person player
+#[derive(Default, Clone)]
+pub struct Person {
+    hand: Vec<Card>,
+    losses: u32
+}
+
+///// Person methods /////
+
+// One line per legal move with where it leaves the total and the risk to the next player, numbered from 1
+pub fn move_lines(state: &GameState, hand: &[Card]) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for m in state.legal_moves(hand) {
+        let declared = match m.play.declared {
+            Some(x) => format!(" as {}", x),
+            None => String::new()
+        };
+        let warning = if m.busts {
+            String::from(" BUST")
+        } else if m.no_safe_reply {
+            String::from(" next player cannot reply")
+        } else {
+            format!(" next player busts {:.0}%", m.reply_bust_chance * 100.0)
+        };
+        lines.push(format!("  {}: {}{} -> {}{}", m.index + 1, m.play.card, declared, m.total, warning));
+    }
+    lines
+}
+
+impl Person {
+    fn play_card(&mut self) -> Card{
+        let mut choose: bool = true;
+        let mut selection: usize = 1;
+        while choose {
+            let mut read = String::new();
+            io::stdin().read_line(&mut read).expect("Invalid input");
+            let input = read.trim();
+            selection = input.parse::<usize>().unwrap_or(0);
+            if selection >= 1 && selection <= self.hand.len() {
+                choose = false;
+            }
+        }
+        self.hand.remove(selection - 1)
+    }
+
+    // Ask for the value of a card that can be played more than one way
+    fn declare_value(&mut self, card: &Card, rules: &Ruleset) -> Option<i64> {
+        let (high, low) = rules.choices(card)?;
+        println!("Play {} as {} or {}?", card, high, low);
+        loop {
+            let mut read = String::new();
+            io::stdin().read_line(&mut read).expect("Invalid input");
+            match read.trim().parse::<i64>() {
+                Ok(x) if x == high || x == low => return Some(x),
+                _ => println!("Type {} or {}", high, low)
+            }
+        }
+    }
+
+    // Take turn
+    fn turn_prompt(&mut self, state: &GameState) -> Play {
+        let cards: Vec<String> = self.hand.iter().map(|c| c.to_string()).collect();
+        println!("The current number is: {}\nYou have cards ({})", state.total, cards.join(","));
+        for line in move_lines(state, &self.hand) {
+            println!("{}", line);
+        }
+        println!("Type 1 to {} to pick a card", self.hand.len());
+        let choice: Card = self.play_card();
+        let declared = self.declare_value(&choice, state.rules);
+        Play { card: choice, declared }
+    }
+
+}
+
+impl Player for Person {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.losses
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.turn_prompt(state)
+    }
+}
+
+//////////////////
+/// Basic Bot ///
+////////////////
+#[derive(Default, Clone)]
+pub struct BasicBot {
+    hand: Vec<Card>,
+    losses: u32,
+    priorities: [usize; 14]
+}
+
+impl BasicBot {
+    // Hand picked priorities, the basic bot every trainer and benchmark measures against
+    pub const REFERENCE: [usize; 14] = [11,12,13,7,6,5,4,3,2,1,0,8,9,10];
+
+    // Plays the first card in priorities order that does not bust
+    pub fn new(priorities: [usize; 14]) -> BasicBot {
+        BasicBot { hand: vec![], losses: 0, priorities }
+    }
+
+    pub fn reference() -> BasicBot {
+        BasicBot::new(BasicBot::REFERENCE)
+    }
+
+    fn randomize(&mut self, rng: &mut StdRng) -> i32 {
+        for i in 0..14 {
+            self.priorities[i] = i;
+        }
+        self.priorities.shuffle(rng);
+        0
+    }
+
+    fn play_card(&mut self, state: &GameState) -> Play {
+        let moves = state.legal_moves(&self.hand);
+        for card in self.priorities {
+            if let Some(m) = moves.iter().find(|m| m.play.card.num_form() == card as i32 && !m.busts) {
+                self.hand.remove(m.index);
+                return m.play.clone();
+            }
+        }
+        self.hand.remove(0);
+        moves[0].play.clone()
+    }
+
+}
+
+impl Player for BasicBot {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.losses
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    fn rand(&mut self, rng: &mut StdRng) {
+        self.randomize(rng);
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+////////////
+/// Deck //
+//////////
+pub fn build_deck() -> Vec<Card>{
+
+    let mut deck: Vec<Card> = Vec::new();
+    for _i in 0..2 {
+        for c in [Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
+        Card::Queen, Card::King, Card::OneEye] {
+        deck.push(c)
+        };
+    };
+    for _i in 0..2 {
+        for c in [Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
+        Card::Jack, Card::Queen, Card::King] {
+            deck.push(c)
+        };
+    };
+    deck
+}
+
+// What goes back into the deck when it runs out
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ReshufflePolicy {
+    // Every discarded card is shuffled back in
+    WholeDiscard,
+    // The most recent n discards stay face up, the rest are shuffled back in
+    KeepLast(usize)
+}
+
+// Count of each card indexed by Card::num_form
+pub fn composition(cards: &[Card]) -> [usize; 14] {
+    let mut counts = [0; 14];
+    for c in cards.iter() {
+        counts[c.num_form() as usize] += 1;
+    }
+    counts
+}
+
+// Draw pile and discard pile of one game, every card of build_deck is always in exactly one
+// of the two or in a hand
+pub(crate) struct Deck {
+    cards: Vec<Card>,
+    discarded: Vec<Card>,
+    counts: [usize; 14],
+    policy: ReshufflePolicy,
+    pub(crate) reshuffles: usize
+}
+
+impl Deck {
+    pub(crate) fn new(policy: ReshufflePolicy, rng: &mut StdRng) -> Deck {
+        let mut cards = build_deck();
+        cards.shuffle(rng);
+        Deck { cards, discarded: Vec::new(), counts: [0; 14], policy, reshuffles: 0 }
+    }
+
+    // Take the top card, reshuffling the discard first if the deck is empty
+    // None only when every card is held or kept on the discard pile
+    pub(crate) fn draw(&mut self, rng: &mut StdRng) -> Option<Card> {
+        if self.cards.is_empty() {
+            self.reshuffle(rng);
+        }
+        self.cards.pop()
+    }
+
+    pub(crate) fn discard(&mut self, card: Card) {
+        self.counts[card.num_form() as usize] += 1;
+        self.discarded.push(card);
+    }
+
+    // Move the discard back into the deck under the policy, the running counts then only cover what stayed out
+    fn reshuffle(&mut self, rng: &mut StdRng) {
+        let keep: usize = match self.policy {
+            ReshufflePolicy::WholeDiscard => 0,
+            ReshufflePolicy::KeepLast(n) => n.min(self.discarded.len())
+        };
+        // With every discard kept nothing goes back, the deck stays empty and there is no reshuffle
+        if keep == self.discarded.len() {
+            return;
+        }
+        let kept: Vec<Card> = self.discarded.split_off(self.discarded.len() - keep);
+        self.cards.append(&mut self.discarded);
+        self.cards.shuffle(rng);
+        self.counts = [0; 14];
+        for card in kept {
+            self.discard(card);
+        }
+        self.reshuffles += 1;
+    }
+
+    // Cards played since the last reshuffle, the running count bots see
+    fn counts(&self) -> &[usize; 14] {
+        &self.counts
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cards.len()
+    }
+
+    // True when deck, discard and the given hands together hold exactly the build_deck cards
+    pub(crate) fn conserved(&self, hands: Vec<&Vec<Card>>) -> bool {
+        let mut all: Vec<Card> = self.cards.clone();
+        all.extend(self.discarded.iter().cloned());
+        for hand in hands {
+            all.extend(hand.iter().cloned());
+        }
+        composition(&all) == composition(&build_deck())
+    }
+}
+//////////////
+/// Moves ///
+////////////
+// One way to play a card from hand and where it leaves the table
+#[derive(Clone)]
+pub struct Move {
+    pub index: usize,
+    pub play: Play,
+    pub total: i64,
+    pub busts: bool,
+    // Chance the next player holds nothing that keeps the total at or under 99
+    pub reply_bust_chance: f64,
+    pub no_safe_reply: bool
+}
+
+// Cards the player has not seen: the full deck minus the discard counts and their own hand
+pub fn unseen_cards(hand: &[Card], counts: &[usize; 14]) -> [usize; 14] {
+    let mut unseen = composition(&build_deck());
+    for i in 0..14 {
+        unseen[i] = unseen[i].saturating_sub(counts[i]);
+    }
+    for card in hand.iter() {
+        unseen[card.num_form() as usize] = unseen[card.num_form() as usize].saturating_sub(1);
+    }
+    unseen
+}
+
+// Chance that hand_size cards drawn from unseen hold no card that is safe on total
+pub fn reply_bust_chance(total: i64, unseen: &[usize; 14], hand_size: usize, rules: &Ruleset) -> f64 {
+    let pool: usize = unseen.iter().sum();
+    let safe: usize = (0..14).filter(|&i| rules.new_number(&Card::from_num_form(i), total) <= 99).map(|i| unseen[i]).sum();
+    let unsafe_cards: usize = pool - safe;
+    if hand_size > pool {
+        return if safe == 0 { 1.0 } else { 0.0 };
+    }
+    let mut chance: f64 = 1.0;
+    for i in 0..hand_size {
+        if unsafe_cards < i + 1 {
+            return 0.0;
+        }
+        chance *= (unsafe_cards - i) as f64 / (pool - i) as f64;
+    }
+    chance
+}
+
+impl<'a> GameState<'a> {
+    // Every way to play the hand on the total, flexible cards get one move per value with the default first
+    // The next player is assumed to hold as many cards as this hand
+    pub fn legal_moves(&self, hand: &[Card]) -> Vec<Move> {
+        let rules = self.rules;
+        let unseen = unseen_cards(hand, self.counts);
+        let mut moves: Vec<Move> = Vec::new();
+        for (index, card) in hand.iter().enumerate() {
+            let declared: Vec<Option<i64>> = match (rules.choices(card), rules.default_value(card, self.total)) {
+                (Some((high, low)), Some(x)) => vec![Some(x), Some(if x == high { low } else { high })],
+                _ => vec![None]
+            };
+            for value in declared {
+                let play = Play { card: card.clone(), declared: value };
+                let total = rules.play_number(&play, self.total);
+                let busts = total > 99;
+                let chance = if busts { 0.0 } else { reply_bust_chance(total, &unseen, hand.len(), rules) };
+                moves.push(Move { index, play, total, busts, reply_bust_chance: chance, no_safe_reply: !busts && chance >= 1.0 });
+            }
+        }
+        moves
+    }
+}
+
+
+////////////////
+/// Game Log ///
+//////////////
+// One thing that happened at the table, written one per line as a JSON object
+#[derive(Clone)]
+pub enum GameEvent {
+    Start { players: usize, first: usize, rules: String },
+    Deal { seat: usize, hand: Vec<Card> },
+    Play { seat: usize, hand: Vec<Card>, card: Card, declared: Option<i64>, total: i64, direction: i64 },
+    Reverse { direction: i64 },
+    Reshuffle { deck: usize },
+    Bust { seat: usize, total: i64 }
+}
+
+// A value inside a flat log line
+pub enum JsonValue {
+    Str(String),
+    Num(i64),
+    Real(f64),
+    List(Vec<String>),
+    Null
+}
+
+pub fn hand_to_json(hand: &[Card]) -> String {
+    let names: Vec<String> = hand.iter().map(|c| format!("\"{}\"", c)).collect();
+    format!("[{}]", names.join(","))
+}
+
+pub fn hand_from_json(value: Option<&JsonValue>) -> Option<Vec<Card>> {
+    match value? {
+        JsonValue::List(names) => names.iter().map(|n| Card::from_name(n)).collect(),
+        _ => None
+    }
+}
+
+pub fn num_from_json(value: Option<&JsonValue>) -> Option<i64> {
+    match value? {
+        JsonValue::Num(x) => Some(*x),
+        _ => None
+    }
+}
+
+// Integers are accepted wherever a real number is expected
+pub fn real_from_json(value: Option<&JsonValue>) -> Option<f64> {
+    match value? {
+        JsonValue::Num(x) => Some(*x as f64),
+        JsonValue::Real(x) => Some(*x),
+        _ => None
+    }
+}
+
+pub fn str_from_json(value: Option<&JsonValue>) -> Option<String> {
+    match value? {
+        JsonValue::Str(x) => Some(x.clone()),
+        _ => None
+    }
+}
+
+// Read a quoted string starting at chars[*pos], names in the log never contain escapes
+fn parse_json_str(chars: &[char], pos: &mut usize) -> Option<String> {
+    if chars.get(*pos) != Some(&'"') {
+        return None;
+    }
+    *pos += 1;
+    let mut out = String::new();
+    while *chars.get(*pos)? != '"' {
+        out.push(chars[*pos]);
+        *pos += 1;
+    }
+    *pos += 1;
+    Some(out)
+}
+
+// Parse a flat JSON object of strings, numbers, null and lists of strings
+pub fn parse_json_line(line: &str) -> Option<HashMap<String, JsonValue>> {
+    let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut fields: HashMap<String, JsonValue> = HashMap::new();
+    let mut pos: usize = 0;
+    if chars.get(pos) != Some(&'{') {
+        return None;
+    }
+    pos += 1;
+    while *chars.get(pos)? != '}' {
+        let key = parse_json_str(&chars, &mut pos)?;
+        if chars.get(pos) != Some(&':') {
+            return None;
+        }
+        pos += 1;
+        let value = match chars.get(pos)? {
+            '"' => JsonValue::Str(parse_json_str(&chars, &mut pos)?),
+            '[' => {
+                pos += 1;
+                let mut items: Vec<String> = Vec::new();
+                while *chars.get(pos)? != ']' {
+                    items.push(parse_json_str(&chars, &mut pos)?);
+                    if chars.get(pos) == Some(&',') {
+                        pos += 1;
+                    }
+                }
+                pos += 1;
+                JsonValue::List(items)
+            },
+            'n' => {
+                pos += 4;
+                JsonValue::Null
+            },
+            _ => {
+                let start = pos;
+                while chars.get(pos).is_some_and(|c| *c == '-' || *c == '+' || *c == '.' || *c == 'e' || *c == 'E' || c.is_ascii_digit()) {
+                    pos += 1;
+                }
+                let number: String = chars[start..pos].iter().collect();
+                match number.parse::<i64>() {
+                    Ok(x) => JsonValue::Num(x),
+                    Err(_) => JsonValue::Real(number.parse::<f64>().ok()?)
+                }
+            }
+        };
+        fields.insert(key, value);
+        if chars.get(pos) == Some(&',') {
+            pos += 1;
+        }
+    }
+    Some(fields)
+}
+
+impl GameEvent {
+    pub fn to_json(&self) -> String {
+        match self {
+            GameEvent::Start { players, first, rules } =>
+                format!("{{\"event\":\"start\",\"players\":{},\"first\":{},\"rules\":\"{}\"}}", players, first, rules),
+            GameEvent::Deal { seat, hand } =>
+                format!("{{\"event\":\"deal\",\"seat\":{},\"hand\":{}}}", seat, hand_to_json(hand)),
+            GameEvent::Play { seat, hand, card, declared, total, direction } => {
+                let declared = match declared {
+                    Some(x) => x.to_string(),
+                    None => String::from("null")
+                };
+                format!("{{\"event\":\"play\",\"seat\":{},\"hand\":{},\"card\":\"{}\",\"declared\":{},\"total\":{},\"direction\":{}}}",
+                    seat, hand_to_json(hand), card, declared, total, direction)
+            },
+            GameEvent::Reverse { direction } =>
+                format!("{{\"event\":\"reverse\",\"direction\":{}}}", direction),
+            GameEvent::Reshuffle { deck } =>
+                format!("{{\"event\":\"reshuffle\",\"deck\":{}}}", deck),
+            GameEvent::Bust { seat, total } =>
+                format!("{{\"event\":\"bust\",\"seat\":{},\"total\":{}}}", seat, total)
+        }
+    }
+
+    pub fn from_json(line: &str) -> Option<GameEvent> {
+        let fields = parse_json_line(line)?;
+        let seat = || num_from_json(fields.get("seat")).map(|x| x as usize);
+        match str_from_json(fields.get("event"))?.as_str() {
+            "start" => Some(GameEvent::Start {
+                players: num_from_json(fields.get("players"))? as usize,
+                first: num_from_json(fields.get("first"))? as usize,
+                rules: str_from_json(fields.get("rules"))?
+            }),
+            "deal" => Some(GameEvent::Deal { seat: seat()?, hand: hand_from_json(fields.get("hand"))? }),
+            "play" => Some(GameEvent::Play {
+                seat: seat()?,
+                hand: hand_from_json(fields.get("hand"))?,
+                card: Card::from_name(&str_from_json(fields.get("card"))?)?,
+                declared: num_from_json(fields.get("declared")),
+                total: num_from_json(fields.get("total"))?,
+                direction: num_from_json(fields.get("direction"))?
+            }),
+            "reverse" => Some(GameEvent::Reverse { direction: num_from_json(fields.get("direction"))? }),
+            "reshuffle" => Some(GameEvent::Reshuffle { deck: num_from_json(fields.get("deck"))? as usize }),
+            "bust" => Some(GameEvent::Bust { seat: seat()?, total: num_from_json(fields.get("total"))? }),
+            _ => None
+        }
+    }
+}
+
+// The events of one or more games in the order they happened
+#[derive(Default)]
+pub struct GameLog {
+    pub events: Vec<GameEvent>
+}
+
+impl GameLog {
+    fn push(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    // Append every event to the file at path as line based JSON
+    pub fn write_jsonl(&self, path: &str) -> io::Result<()> {
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        for event in self.events.iter() {
+            writeln!(file, "{}", event.to_json())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_jsonl(path: &str) -> io::Result<GameLog> {
+        let text = std::fs::read_to_string(path)?;
+        let mut log = GameLog::default();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match GameEvent::from_json(line) {
+                Some(event) => log.push(event),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}:{} is not a game event", path, number + 1)))
+            }
+        }
+        Ok(log)
+    }
+}
+
+
+/////////////
+/// GAME ///
+///////////
+// Plays after which a game is called, the seat to move then busts so a stalled table still ends the same way
+pub const MAX_PLAYS: usize = 1000;
+
+// Main game run sequence, will return the losing player
+// When a log is given every deal, play, reversal, reshuffle and the bust are appended to it
+pub fn run_game(players: &mut Vec<&mut dyn Player>, first_player: i64, rules: &Ruleset, rng: &mut StdRng, mut log: Option<&mut GameLog>) -> i64{
+    let num_players: usize = players.len();
+    if players.is_empty() {
+        return -1;
+    }
+    let mut deck: Deck = Deck::new(rules.reshuffle, rng);
+    let mut history: Vec<Play> = Vec::new();
+    let mut current_player = first_player;
+    let mut current_number: i64 = 0;
+    let mut direction: i64 = 1;
+    let mut end: bool = false;
+
+    for player in players.iter_mut() {
+        player.clear_hand();
+    }
+
+    for _i in 0..3 {
+        for player in players.iter_mut() {
+            if let Some(card) = deck.draw(rng) {
+                player.take_card(card)
+            }
+        }
+    }
+
+    if let Some(log) = log.as_deref_mut() {
+        log.push(GameEvent::Start { players: num_players, first: first_player as usize, rules: rules.name.to_string() });
+        for (j, player) in players.iter().enumerate() {
+            log.push(GameEvent::Deal { seat: j, hand: player.hand().clone() });
+        }
+    }
+
+    while !end {
+        // A seat left with no cards by a short deck has nothing to play and busts, as does the seat to move
+        // once the game runs past MAX_PLAYS
+        if players[current_player as usize].hand().is_empty() || history.len() >= MAX_PLAYS {
+            break;
+        }
+        let hand: Vec<Card> = match log {
+            Some(_) => players[current_player as usize].hand().clone(),
+            None => Vec::new()
+        };
+        let state = GameState {
+            total: current_number,
+            direction,
+            seat: current_player as usize,
+            num_players,
+            counts: deck.counts(),
+            history: &history,
+            deck_size: deck.len(),
+            rules
+        };
+        let played = players[current_player as usize].take_turn(&state);
+        let play_copy = played.card.clone();
+        deck.discard(played.card.clone());
+        let reversed = rules.reverses(&play_copy);
+        if reversed {
+        direction = -direction;
+        }
+        current_number = rules.play_number(&played, current_number);
+        history.push(played.clone());
+        if let Some(log) = log.as_deref_mut() {
+            log.push(GameEvent::Play { seat: current_player as usize, hand, card: play_copy.clone(), declared: played.declared, total: current_number, direction });
+            if reversed {
+                log.push(GameEvent::Reverse { direction });
+            }
+        }
+        if current_number > 99 {
+            end = true;
+        } else {
+        current_player += direction;
+        if current_player >= num_players as i64{
+            current_player = 0;
+        } else if current_player <= -1 {
+            current_player = num_players as i64 - 1;
+        }
+        let reshuffles = deck.reshuffles;
+        let drawn = deck.draw(rng);
+        if deck.reshuffles != reshuffles {
+            if let Some(log) = log.as_deref_mut() {
+                // The size of the fresh deck, counting the card just drawn from it
+                log.push(GameEvent::Reshuffle { deck: deck.len() + drawn.iter().count() });
+            }
+        }
+        if let Some(card) = drawn {
+            players[current_player as usize].take_card(card);
+        }
+        debug_assert!(deck.conserved(players.iter().map(|p| p.hand()).collect()), "cards were created or lost");
+        }
+    }
+    if let Some(log) = log {
+        log.push(GameEvent::Bust { seat: current_player as usize, total: current_number });
+    }
+    players[current_player as usize].lose();
+    current_player
+}
+
+// Elimination match, every seat starts with tokens and loses one per bust until one seat remains
+// The first player rotates each game, returns the seats in finishing order with the winner first
+pub fn run_match(players: &mut Vec<&mut dyn Player>, tokens: u32, first_player: usize, rules: &Ruleset, rng: &mut StdRng, mut log: Option<&mut GameLog>) -> Vec<usize> {
+    let num_players: usize = players.len();
+    if num_players == 0 {
+        return vec![];
+    }
+    let mut lives: Vec<u32> = vec![tokens.max(1); num_players];
+    let mut eliminated: Vec<usize> = Vec::new();
+    let mut first: usize = first_player % num_players;
+
+    while eliminated.len() + 1 < num_players {
+        let seats: Vec<usize> = (0..num_players).filter(|&s| lives[s] > 0).collect();
+        while lives[first] == 0 {
+            first = (first + 1) % num_players;
+        }
+        let table_first = seats.iter().position(|&s| s == first).unwrap();
+        let mut table: Vec<&mut dyn Player> = players.iter_mut().enumerate().filter(|(s, _)| lives[*s] > 0).map(|(_, p)| &mut **p as &mut dyn Player).collect();
+
+        let loser = seats[run_game(&mut table, table_first as i64, rules, rng, log.as_deref_mut()) as usize];
+        lives[loser] -= 1;
+        if lives[loser] == 0 {
+            eliminated.push(loser);
+        }
+        first = (first + 1) % num_players;
+    }
+
+    let mut order: Vec<usize> = (0..num_players).filter(|&s| lives[s] > 0).collect();
+    eliminated.reverse();
+    order.append(&mut eliminated);
+    order
+}
+
+// Seed for one job of a parallel run, so results do not depend on which thread plays which job
+pub fn job_seed(master: u64, job: usize) -> u64 {
+    // splitmix64 of the job index mixed into the master seed
+    let mut z: u64 = master.wrapping_add((job as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// Run num_jobs jobs across every core, each with its own rng seeded from master and the job index
+// Results come back in job order
+fn run_parallel<T: Send, F: Fn(usize, &mut StdRng) -> T + Sync>(num_jobs: usize, master: u64, job: F) -> Vec<T> {
+    let threads: usize = std::thread::available_parallelism().map_or(1, |n| n.get()).min(num_jobs.max(1));
+    let job = &job;
+    let mut done: Vec<(usize, T)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads).map(|t| scope.spawn(move || {
+            let mut out: Vec<(usize, T)> = Vec::new();
+            for k in (t..num_jobs).step_by(threads) {
+                let mut rng = StdRng::seed_from_u64(job_seed(master, k));
+                out.push((k, job(k, &mut rng)));
+            }
+            out
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().expect("Evaluation thread panicked")).collect()
+    });
+    done.sort_by_key(|(k, _)| *k);
+    done.into_iter().map(|(_, t)| t).collect()
+}
+
+// Create a function that will run two sets of players against each other, facing each player against each player on the other set N times
+// Each meeting is an elimination match with the given tokens per seat, one token plays a single game
+// A loss is a match lost, however many tokens it took, so losses never exceed the matches played
+// Meetings run in parallel, each on its own copies of the two players
+pub fn run_team_round_robin_1v1(team1: Vec<Box<dyn Player>>, team2: Vec<Box<dyn Player>>, num_games: usize, tokens: u32, rules: &Ruleset, rng: &mut StdRng) -> Vec<Vec<usize>> {
+    let width: usize = team2.len();
+    let length: usize = team1.len();
+    // Players need not be Sync, so the jobs take their copies under a lock
+    let shared1: Vec<Mutex<Box<dyn Player>>> = team1.into_iter().map(Mutex::new).collect();
+    let shared2: Vec<Mutex<Box<dyn Player>>> = team2.into_iter().map(Mutex::new).collect();
+    let outcomes: Vec<(u32, u32)> = run_parallel(length * width, rng.gen(), |job, job_rng| {
+        let mut p1 = shared1[job / width].lock().unwrap().clone();
+        let mut p2 = shared2[job % width].lock().unwrap().clone();
+        let mut lost: (u32, u32) = (0, 0);
+        for k in 0..num_games {
+            let mut players: Vec<&mut dyn Player> = vec![&mut *p1, &mut *p2];
+            match run_match(&mut players, tokens, k % 2, rules, job_rng, None).first() {
+                Some(0) => lost.1 += 1,
+                _ => lost.0 += 1
+            }
+        }
+        lost
+    });
+    let mut team1: Vec<Box<dyn Player>> = shared1.into_iter().map(|p| p.into_inner().unwrap()).collect();
+    let mut team2: Vec<Box<dyn Player>> = shared2.into_iter().map(|p| p.into_inner().unwrap()).collect();
+    for (job, (lost1, lost2)) in outcomes.into_iter().enumerate() {
+        team1[job / width].add_losses(lost1);
+        team2[job % width].add_losses(lost2);
+    }
+    let team1_losses: Vec<usize> = team1.iter().map(|f| f.loss_count() as usize).collect::<Vec<usize>>();
+    let team2_losses: Vec<usize> = team2.iter().map(|f| f.loss_count() as usize).collect::<Vec<usize>>();
+    
+    vec![team1_losses, team2_losses]
+}
+
+// Losses and matches for every player by team, and losses by starting seat across all tables
+// A loss is going out first, so a player loses at most once per match at any table size
+pub struct TableResults {
+    pub losses: Vec<Vec<usize>>,
+    pub games: Vec<Vec<usize>>,
+    pub seat_losses: Vec<usize>
+}
+
+// Run tables of table_size drawn from several teams
+// Seat s of every table belongs to team s % teams, so each team has a seat at every table. Each round every team
+// is shuffled and dealt into its seats, there are as many tables as the largest team needs and a team that runs
+// out starts again from the top of its order, so no one sits out and a lone opponent sits at every table
+// Every table plays games_per_table matches, rotating the seating one place per match so each player starts in
+// turn, and reversing the seating every full rotation so each player also follows each neighbour
+// The tables of a round run in parallel, each on its own copies of its players
+pub fn run_table_tournament(teams: Vec<Vec<Box<dyn Player>>>, table_size: usize, rounds: usize, games_per_table: usize, tokens: u32, rules: &Ruleset, rng: &mut StdRng) -> TableResults {
+    let team_sizes: Vec<usize> = teams.iter().map(|t| t.len()).collect();
+    // Players need not be Sync, so the tables take their copies under a lock
+    let pool: Vec<Mutex<Box<dyn Player>>> = teams.into_iter().flatten().map(Mutex::new).collect();
+    let mut results = TableResults {
+        losses: team_sizes.iter().map(|&n| vec![0; n]).collect(),
+        games: team_sizes.iter().map(|&n| vec![0; n]).collect(),
+        seat_losses: vec![0; table_size]
+    };
+    // (team, index) of every player in the pool
+    let owners: Vec<(usize, usize)> = team_sizes.iter().enumerate().flat_map(|(t, &n)| (0..n).map(move |i| (t, i))).collect();
+    let num_teams = team_sizes.len();
+    // Seats each team fills at one table
+    let team_seats: Vec<usize> = (0..num_teams).map(|t| (0..table_size).filter(|s| s % num_teams == t).count()).collect();
+    if table_size < 2 || team_sizes.iter().zip(team_seats.iter()).any(|(&n, &seats)| n == 0 && seats > 0) {
+        return results;
+    }
+    let num_tables: usize = team_sizes.iter().zip(team_seats.iter()).filter(|(_, &seats)| seats > 0).map(|(&n, &seats)| n.div_ceil(seats)).max().unwrap_or(0);
+
+    for _round in 0..rounds {
+        let mut shuffled: Vec<Vec<usize>> = Vec::new();
+        let mut offset: usize = 0;
+        for &n in team_sizes.iter() {
+            let mut members: Vec<usize> = (offset..offset + n).collect();
+            members.shuffle(rng);
+            shuffled.push(members);
+            offset += n;
+        }
+        // The k-th seat a team fills this round goes to its k-th member, wrapping round a short team
+        let mut dealt: Vec<usize> = vec![0; num_teams];
+        let seating: Vec<Vec<usize>> = (0..num_tables).map(|_| {
+            (0..table_size).map(|s| {
+                let team = s % num_teams;
+                let p = shuffled[team][dealt[team] % team_sizes[team]];
+                dealt[team] += 1;
+                p
+            }).collect()
+        }).collect();
+        let tables: Vec<&[usize]> = seating.iter().map(|t| t.as_slice()).collect();
+
+        // For each table, the seating and losses per seat of every match
+        let played: Vec<Vec<(Vec<usize>, Vec<usize>)>> = run_parallel(tables.len(), rng.gen(), |job, job_rng| {
+            let table = tables[job];
+            let mut seated: Vec<Box<dyn Player>> = table.iter().map(|&p| pool[p].lock().unwrap().clone()).collect();
+            let mut matches: Vec<(Vec<usize>, Vec<usize>)> = Vec::new();
+            for g in 0..games_per_table {
+                // Positions into seated, rotated and reversed for this match
+                let mut order: Vec<usize> = (0..table_size).collect();
+                order.rotate_left(g % table_size);
+                if (g / table_size) % 2 == 1 {
+                    order[1..].reverse();
+                }
+                let mut slots: Vec<Option<&mut dyn Player>> = seated.iter_mut().map(|p| Some(&mut **p as &mut dyn Player)).collect();
+                let mut players: Vec<&mut dyn Player> = order.iter().map(|&o| slots[o].take().unwrap()).collect();
+                let finish = run_match(&mut players, tokens, 0, rules, job_rng, None);
+                let lost: Vec<usize> = (0..table_size).map(|seat| if finish.last() == Some(&seat) { 1 } else { 0 }).collect();
+                matches.push((order.iter().map(|&o| table[o]).collect(), lost));
+            }
+            matches
+        });
+
+        for (seating, lost) in played.into_iter().flatten() {
+            for (seat, &p) in seating.iter().enumerate() {
+                let (team, index) = owners[p];
+                results.losses[team][index] += lost[seat];
+                results.games[team][index] += 1;
+                results.seat_losses[seat] += lost[seat];
+            }
+        }
+    }
+    results
+}
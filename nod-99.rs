@@ -1,465 +1,2885 @@
-use std::sync::Arc;
 use std::{io, vec};
+use std::io::{BufRead, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
 use std::collections::HashMap;
 use ::Nod::Network;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::time;
 use Nod::Agency;    
+// Beyond the first version, nod-99 needs Network::get_weights and set_weights, the flat weight vector of a network,
+// and Agency's agents with their score and network, for seeded breeding, checkpoints, diversity and self-play
+
+// Cards, rules, players and the game and tournament runners
+pub mod engine;
+use engine::*;
+
+/////////////////
+/// Encoders ///
+///////////////
+// Turns a hand and the table into network inputs. A model only works with the encoder it was trained with,
+// so every encoder has a tag with its version, and changing an encoder's inputs means a new tag
+pub trait Encoder: Send + Sync {
+    fn tag(&self) -> &'static str;
+    fn inputs(&self) -> usize;
+    fn encode(&self, hand: &[Card], state: &GameState) -> Vec<f64>;
+    // A short name for each input, in encode order
+    fn names(&self) -> Vec<String>;
+}
+
+// Every rank in num_form order
+fn all_ranks() -> Vec<Card> {
+    (0..14).map(Card::from_num_form).collect()
+}
+
+// The original inputs: direction, 1/players, discards/4 and ln(held + 1) by rank, total/99, and the near 99 flags
+pub struct ClassicEncoder;
+
+impl Encoder for ClassicEncoder {
+    fn tag(&self) -> &'static str {
+        "classic-v1"
+    }
+
+    fn inputs(&self) -> usize {
+        35
+    }
+
+    fn names(&self) -> Vec<String> {
+        // encode lists the ranks from Ace with OneEye last
+        let order: Vec<Card> = (1..15).map(|r| Card::from_num_form(r % 14)).collect();
+        let mut names: Vec<String> = vec![String::from("direction"), String::from("1/players")];
+        names.extend(order.iter().map(|c| format!("discarded {}", c)));
+        names.extend(order.iter().map(|c| format!("held {}", c)));
+        for name in ["total/99", "total is 99", "total above 89", "total past 90", "total band"].iter() {
+            names.push(name.to_string());
+        }
+        names
+    }
+
+    fn encode(&self, hand: &[Card], state: &GameState) -> Vec<f64> {
+        let mut inputs: Vec<f64> = vec![state.direction as f64, 1.0 / (state.num_players as f64)];
+        let mut used: Vec<f64> = vec![Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
+        Card::Jack, Card::Queen, Card::King, Card::OneEye].iter().map(|x| (state.counts[x.num_form() as usize] as f64) / 4.0).collect();
+        let mut held: Vec<f64> = vec![Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
+        Card::Jack, Card::Queen, Card::King, Card::OneEye].iter().map(|x| (hand.iter().filter(|&y| y == x).count() as f64 + 1.0).ln()).collect();
+        inputs.append(&mut used);
+        inputs.append(&mut held);
+        inputs.push(state.total as f64 / 99.0);
+        if state.total > 89 {
+            if state.total > 98 {
+                inputs.push(1.0)
+            } else {
+                inputs.push(0.0)
+            }
+            inputs.push(1.0);
+            inputs.push((state.total as f64 - 90.0) / 9.0);
+        } else {
+            inputs.push(0.0);
+            inputs.push(0.0);
+            inputs.push(0.0)
+        }
+        inputs.push(match state.total {
+            x if x < 80 => -1.0,
+            x if x > 79 && x < 90 => 0.0,
+            x if x > 89 => 1.0,
+            _ => 0.0
+        });
+        inputs
+    }
+}
+
+// The classic inputs plus, for every rank, whether playing it now would bust under the table's rules
+pub struct BustsEncoder;
+
+impl Encoder for BustsEncoder {
+    fn tag(&self) -> &'static str {
+        "busts-v1"
+    }
+
+    fn inputs(&self) -> usize {
+        49
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names = ClassicEncoder.names();
+        names.extend(all_ranks().iter().map(|c| format!("{} busts", c)));
+        names
+    }
+
+    fn encode(&self, hand: &[Card], state: &GameState) -> Vec<f64> {
+        let mut inputs = ClassicEncoder.encode(hand, state);
+        for rank in 0..14 {
+            inputs.push(if state.rules.new_number(&Card::from_num_form(rank), state.total) > 99 { 1.0 } else { 0.0 });
+        }
+        inputs
+    }
+}
+
+pub fn encoder_by_tag(tag: &str) -> Option<&'static dyn Encoder> {
+    match tag {
+        "classic-v1" => Some(&ClassicEncoder),
+        "busts-v1" => Some(&BustsEncoder),
+        _ => None
+    }
+}
 
-/////////////////////
-/// Card Section ///
-///////////////////
-// Declare Card enum
-#[derive(PartialEq, Eq, Hash)]
-enum Card {
-    Ace,
-    Two,
-    Three,
-    Four,
-    Five,
-    Six,
-    Seven,
-    Eight,
-    Nine,
-    Ten,
-    Jack,
-    Queen,
-    King,
-    OneEye
-}
-
-impl Card {
-    fn new_number (&self, number: i64) -> i64 {
-        match self {
-            Card::Ace => number + 1,
-            Card::Two => number + 2,
-            Card::Three => number + 3,
-            Card::Four => number + 4,
-            Card::Five => number + 5,
-            Card::Six => number + 6,
-            Card::Seven => number + 7,
-            Card::Eight => number,
-            Card::Nine => number,
-            Card::Ten => number - 10,
-            Card::Jack => number + 10,
-            Card::Queen => number + 10,
-            Card::King => number + 10,
-            Card::OneEye => 99
-        }
-    }
-
-    fn num_form (&self) -> i32 {
-        match self {
-            Card::Ace => 1,
-            Card::Two => 2,
-            Card::Three => 3,
-            Card::Four => 4,
-            Card::Five => 5,
-            Card::Six => 6,
-            Card::Seven => 7,
-            Card::Eight => 8,
-            Card::Nine => 9,
-            Card::Ten => 10,
-            Card::Jack => 11,
-            Card::Queen => 12,
-            Card::King => 13,
-            Card::OneEye => 0
-        }
-    }
-}
-
-// Implement a string format for each card
-impl std::fmt::Display for Card {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Card::Ace => write!(f, "Ace"),
-            Card::Two => write!(f, "Two"),
-            Card::Three => write!(f, "Three"),
-            Card::Four => write!(f, "Four"),
-            Card::Five => write!(f, "Five"),
-            Card::Six => write!(f, "Six"),
-            Card::Seven => write!(f, "Seven"),
-            Card::Eight => write!(f, "Eight"),
-            Card::Nine => write!(f, "Nine"),
-            Card::Ten => write!(f, "Ten"),
-            Card::Jack => write!(f, "Jack"),
-            Card::Queen => write!(f, "Queen"),
-            Card::King => write!(f, "King"),
-            Card::OneEye => write!(f, "OneEye")
-        }
-    }
-}
-
-// Implement clone for Card
-impl Clone for Card {
-    fn clone(&self) -> Card{
-        match self {
-            Card::Ace => Card::Ace,
-            Card::Two => Card::Two,
-            Card::Three => Card::Three,
-            Card::Four => Card::Four,
-            Card::Five => Card::Five,
-            Card::Six => Card::Six,
-            Card::Seven => Card::Seven,
-            Card::Eight => Card::Eight,
-            Card::Nine => Card::Nine,
-            Card::Ten => Card::Ten,
-            Card::Jack => Card::Jack,
-            Card::Queen => Card::Queen,
-            Card::King => Card::King,
-            Card::OneEye => Card::OneEye
-        }
-    }
-}
-
-
-
-//////////////////////
-// PLAYERS SECTION //
 ////////////////////
-/// 
+/// Model Files ///
+//////////////////
+// A model is one name.nod file: a header, Nod's three text files as sections, and a checksum of everything above it
+//   nod99-model 1
+//   encoder classic-v1
+//   hidden tanh
+//   output sigmoid
+//   meta generation 199
+//   types 3
+//   ...the lines of name_types
+//   weights 40
+//   ...
+//   connections 40
+//   ...
+//   checksum 8c2f0a61d3e5b794
+// Models from before the file existed, three files and maybe a name_encoder, still load
+const MODEL_VERSION: usize = 1;
+
+// What a model file says about its network, training metadata is free form key and value pairs
+struct ModelHeader {
+    encoder: &'static dyn Encoder,
+    hidden: String,
+    output: String,
+    metadata: Vec<(String, String)>
+}
+
+// 64 bit FNV-1a, enough to catch a truncated or hand edited file
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn model_path(name: &str) -> String {
+    if name.ends_with(".nod") { name.to_string() } else { format!("{}.nod", name) }
+}
+
+// Nod only reads and writes its three files, so models pass through them under a prefix of their own in the temp directory
+fn scratch_prefix() -> String {
+    static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    std::env::temp_dir().join(format!("nod99_{}_{}", std::process::id(), n)).to_string_lossy().to_string()
+}
+
+const MODEL_SECTIONS: [&str; 3] = ["types", "weights", "connections"];
+
+fn save_model(brain: &Network, name: &str, header: &ModelHeader) -> io::Result<()> {
+    let prefix = scratch_prefix();
+    brain.model_to_txt(prefix.clone());
+    let mut text = format!("nod99-model {}\nencoder {}\nhidden {}\noutput {}\n", MODEL_VERSION, header.encoder.tag(), header.hidden, header.output);
+    for (key, value) in header.metadata.iter() {
+        text.push_str(&format!("meta {} {}\n", key, value));
+    }
+    for section in MODEL_SECTIONS.iter() {
+        let file = format!("{}_{}", prefix, section);
+        let body = std::fs::read_to_string(&file)?;
+        std::fs::remove_file(&file)?;
+        text.push_str(&format!("{} {}\n", section, body.lines().count()));
+        for line in body.lines() {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    text.push_str(&format!("checksum {:016x}\n", fnv1a(&text)));
+    // Write beside the old file and swap, as with checkpoints
+    let path = model_path(name);
+    let partial = format!("{}.partial", path);
+    std::fs::write(&partial, text)?;
+    std::fs::rename(&partial, &path)
+}
+
+// The header and sections of a model file, after checking the checksum
+fn read_model_file(path: &str) -> Result<(ModelHeader, Vec<String>), String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let body_end = text.trim_end().rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let stored = text[body_end..].trim().strip_prefix("checksum ").and_then(|x| u64::from_str_radix(x, 16).ok());
+    if stored != Some(fnv1a(&text[..body_end])) {
+        return Err(format!("{} is damaged, its checksum does not match", path));
+    }
+    let bad = |line: usize| format!("{}:{} is not a model file line", path, line + 1);
+    let lines: Vec<&str> = text[..body_end].lines().collect();
+    match lines.first().and_then(|l| l.strip_prefix("nod99-model ")).and_then(|v| v.trim().parse::<usize>().ok()) {
+        Some(MODEL_VERSION) => (),
+        Some(v) => return Err(format!("{} is model file version {}, this build reads version {}", path, v, MODEL_VERSION)),
+        None => return Err(format!("{} is not a model file", path))
+    }
+    let mut tag = String::new();
+    let mut header = ModelHeader { encoder: &ClassicEncoder, hidden: String::new(), output: String::new(), metadata: vec![] };
+    let mut sections: Vec<String> = Vec::new();
+    let mut number: usize = 1;
+    while number < lines.len() {
+        let (key, value) = lines[number].split_once(' ').unwrap_or((lines[number], ""));
+        match key {
+            "encoder" => tag = value.to_string(),
+            "hidden" => header.hidden = value.to_string(),
+            "output" => header.output = value.to_string(),
+            "meta" => {
+                let (k, v) = value.split_once(' ').unwrap_or((value, ""));
+                header.metadata.push((k.to_string(), v.to_string()));
+            },
+            _ if sections.len() < 3 && key == MODEL_SECTIONS[sections.len()] => {
+                let count = value.parse::<usize>().map_err(|_| bad(number))?;
+                if number + count >= lines.len() {
+                    return Err(bad(number));
+                }
+                sections.push(lines[number + 1..number + 1 + count].iter().map(|l| format!("{}\n", l)).collect());
+                number += count;
+            },
+            _ => return Err(bad(number))
+        }
+        number += 1;
+    }
+    if sections.len() < 3 {
+        return Err(format!("{} is missing its {} section", path, MODEL_SECTIONS[sections.len()]));
+    }
+    header.encoder = encoder_by_tag(&tag).ok_or(format!("{} was trained with encoder {}, which this build does not have", path, tag))?;
+    Ok((header, sections))
+}
+
+// Load name.nod, or the three files of an older model with hidden and output as its activations
+// Older models record their encoder in name_encoder, and those from before encoders were recorded used classic-v1
+fn load_model(name: &str, hidden: &str, output: &str) -> Result<(Network, ModelHeader), String> {
+    let path = model_path(name);
+    if std::path::Path::new(&path).exists() {
+        let (header, sections) = read_model_file(&path)?;
+        let prefix = scratch_prefix();
+        for (section, body) in MODEL_SECTIONS.iter().zip(sections.iter()) {
+            std::fs::write(format!("{}_{}", prefix, section), body).map_err(|e| format!("{}: {}", prefix, e))?;
+        }
+        let brain = Nod::model_from_txt(format!("{}_types", prefix), format!("{}_weights", prefix), format!("{}_connections", prefix),
+            header.hidden.clone(), header.output.clone());
+        for section in MODEL_SECTIONS.iter() {
+            let _ = std::fs::remove_file(format!("{}_{}", prefix, section));
+        }
+        return Ok((brain, header));
+    }
+    let tag = match std::fs::read_to_string(format!("{}_encoder", name)) {
+        Ok(text) => text.trim().to_string(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::from("classic-v1"),
+        Err(e) => return Err(format!("could not read the encoder of model {}: {}", name, e))
+    };
+    let encoder = encoder_by_tag(&tag).ok_or(format!("model {} was trained with encoder {}, which this build does not have", name, tag))?;
+    if !std::path::Path::new(&format!("{}_weights", name)).exists() {
+        return Err(format!("there is no model {} or {}_weights", path, name));
+    }
+    let brain = Nod::model_from_txt(format!("{}_types", name), format!("{}_weights", name), format!("{}_connections", name), hidden.to_string(), output.to_string());
+    Ok((brain, ModelHeader { encoder, hidden: hidden.to_string(), output: output.to_string(), metadata: vec![] }))
+}
+
+// Load a model that has to read the inputs of a particular encoder
+fn load_model_for(name: &str, hidden: &str, output: &str, encoder: &dyn Encoder) -> Result<Network, String> {
+    let (brain, header) = load_model(name, hidden, output)?;
+    if header.encoder.tag() != encoder.tag() {
+        return Err(format!("model {} was trained with encoder {} but is being used with {}", name, header.encoder.tag(), encoder.tag()));
+    }
+    Ok(brain)
+}
+
+// Print what a model file says about itself, for nod-99 model
+fn describe_model(name: &str) -> Result<(), String> {
+    let path = model_path(name);
+    let (header, sections) = read_model_file(&path)?;
+    println!("{}: model file version {}, checksum ok", path, MODEL_VERSION);
+    println!("Encoder {} with {} inputs, activations {} and {}", header.encoder.tag(), header.encoder.inputs(), header.hidden, header.output);
+    for (key, value) in header.metadata.iter() {
+        println!("  {} = {}", key, value);
+    }
+    for (section, body) in MODEL_SECTIONS.iter().zip(sections.iter()) {
+        println!("  {} section: {} lines", section, body.lines().count());
+    }
+    Ok(())
+}
+
+////////////////
+/// Nod Bot ///
+//////////////
+#[derive(Clone)]
+pub struct BotNod {
+    hand: Vec<Card>,
+    losses: u32,
+    brain: Network,
+    encoder: &'static dyn Encoder
+}
+
+impl BotNod {
+    // Index in hand of the card the network scores highest, with the declaration heads
+    fn choose(&mut self, state: &GameState) -> (usize, Vec<f64>) {
+        let inputs: Vec<f64> = self.encoder.encode(&self.hand, state);
+        let mut outputs: Vec<f64> = self.brain.process(inputs);
+        // Outputs past the 14 card scores are declaration heads, one per card
+        let heads: Vec<f64> = if outputs.len() > 14 { outputs.split_off(14) } else { vec![] };
+        let mut big: usize = 0;
+        for _ in 0..outputs.len() {
+            for j in 0..outputs.len() {
+                if outputs[j] > outputs[big] {
+                    big = j;
+                }
+            }
+            for j in 0..self.hand.len() {
+                if self.hand[j].num_form() == big as i32 {
+                    return (j, heads);
+                }
+            }
+            outputs[big] = 0.0;
+        }
+        (0, heads)
+    }
+
+    // Select card
+    fn play_card(&mut self, state: &GameState) -> Play {
+        let (index, heads) = self.choose(state);
+        let card = self.hand.remove(index);
+        let declared = BotNod::declare(&card, &heads, state.total, state.rules);
+        Play { card, declared }
+    }
+
+    // A head at or above 0.5 declares the higher value, models trained without heads use the ruleset default
+    fn declare(card: &Card, heads: &[f64], current_number: i64, rules: &Ruleset) -> Option<i64> {
+        let (high, low) = rules.choices(card)?;
+        match heads.get(card.num_form() as usize) {
+            Some(x) if *x >= 0.5 => Some(high),
+            Some(_) => Some(low),
+            None => rules.default_value(card, current_number)
+        }
+    }
+}
+
+// Training under a ruleset with cards played either way needs a model with the declaration heads,
+// without them evolution could never change what is declared
+fn check_heads(outputs: usize, rules: &Ruleset) -> Result<(), String> {
+    if rules.has_choices() && outputs < 28 {
+        Err(format!("the {} ruleset needs models with 28 outputs, 14 card scores then 14 declaration heads, this one has {}", rules.name, outputs))
+    } else {
+        Ok(())
+    }
+}
+
+impl Player for BotNod {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.losses
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+///////////////////
+/// Search Bot ///
+/////////////////
+// Information-set Monte Carlo: deals the unseen cards into possible opponent hands and deck orders,
+// plays each safe move forward many times and keeps the one it busts on least
+#[derive(Clone)]
+pub struct SearchBot {
+    hand: Vec<Card>,
+    losses: u32,
+    simulations: usize,
+    rng: StdRng
+}
+
+impl SearchBot {
+    fn new(simulations: usize, seed: u64) -> SearchBot {
+        SearchBot { hand: vec![], losses: 0, simulations, rng: StdRng::seed_from_u64(seed) }
+    }
+
+    // Play one possible game forward after m, true if this seat is the one that busts
+    // Follows run_game: the next player draws and then plays, opponents hold three cards before drawing,
+    // and everyone plays a random safe card from then on
+    fn rollout(&self, state: &GameState, m: &Move, rng: &mut StdRng) -> bool {
+        let rules = state.rules;
+        let num_players = state.num_players;
+        let unseen = unseen_cards(&self.hand, state.counts);
+        let mut deck: Vec<Card> = Vec::new();
+        for (i, &count) in unseen.iter().enumerate() {
+            for _ in 0..count {
+                deck.push(Card::from_num_form(i));
+            }
+        }
+        deck.shuffle(rng);
+
+        let mut hands: Vec<Vec<Card>> = vec![Vec::new(); num_players];
+        for (seat, held) in hands.iter_mut().enumerate() {
+            if seat == state.seat {
+                *held = self.hand.clone();
+                held.remove(m.index);
+            } else {
+                for _ in 0..3 {
+                    if let Some(card) = deck.pop() {
+                        held.push(card);
+                    }
+                }
+            }
+        }
+        let mut discard: Vec<Card> = vec![m.play.card.clone()];
+        let mut total: i64 = m.total;
+        let mut direction: i64 = if rules.reverses(&m.play.card) { -state.direction } else { state.direction };
+        let mut current: usize = state.seat;
+
+        // Long enough for any real game, a draw counts as safe
+        for _ in 0..1000 {
+            current = (current as i64 + direction).rem_euclid(num_players as i64) as usize;
+            if deck.is_empty() {
+                deck.append(&mut discard);
+                deck.shuffle(rng);
+            }
+            if let Some(card) = deck.pop() {
+                hands[current].push(card);
+            }
+            let safe: Vec<usize> = (0..hands[current].len()).filter(|&i| rules.new_number(&hands[current][i], total) <= 99).collect();
+            let pick = match safe.choose(rng) {
+                Some(&i) => i,
+                None => return current == state.seat
+            };
+            let card = hands[current].remove(pick);
+            total = rules.new_number(&card, total);
+            if rules.reverses(&card) {
+                direction *= -1;
+            }
+            discard.push(card);
+        }
+        false
+    }
+
+    // Split the simulation budget over the safe moves and play the one with the fewest busts
+    // Every move is played out on the same sampled deals so the comparison is not down to luck of the deal
+    fn play_card(&mut self, state: &GameState) -> Play {
+        let moves = state.legal_moves(&self.hand);
+        let safe: Vec<&Move> = moves.iter().filter(|m| !m.busts).collect();
+        let choice: Move = match safe.len() {
+            0 => moves[0].clone(),
+            1 => safe[0].clone(),
+            n => {
+                let deals: Vec<u64> = (0..(self.simulations / n).max(1)).map(|_| self.rng.gen()).collect();
+                let mut best: (usize, usize) = (0, usize::MAX);
+                for (i, m) in safe.iter().enumerate() {
+                    let busts: usize = deals.iter().filter(|&&d| self.rollout(state, m, &mut StdRng::seed_from_u64(d))).count();
+                    if busts < best.1 {
+                        best = (i, busts);
+                    }
+                }
+                safe[best.0].clone()
+            }
+        };
+        self.hand.remove(choice.index);
+        choice.play
+    }
+}
+
+impl Player for SearchBot {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.losses
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    fn rand(&mut self, rng: &mut StdRng) {
+        self.rng = StdRng::seed_from_u64(rng.gen());
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+
+///////////////////
+/// Hybrid Bot ///
+/////////////////
+// Solves the endgame with EndgameSolver once the total is above threshold, the fallback player chooses earlier
+// in the game and whenever the position is too open to solve
+// The hand and losses live in the fallback
+#[derive(Clone)]
+pub struct HybridBot {
+    fallback: Box<dyn Player>,
+    threshold: i64
+}
+
+impl HybridBot {
+    pub fn new(fallback: Box<dyn Player>, threshold: i64) -> HybridBot {
+        HybridBot { fallback, threshold }
+    }
+
+    fn play_card(&mut self, state: &GameState) -> Play {
+        if state.total <= self.threshold {
+            return self.fallback.take_turn(state);
+        }
+        let hand = self.fallback.hand().clone();
+        let mut solver = EndgameSolver::new(state, hand.len());
+        match solver.solve(state, &hand) {
+            Some((m, _)) => {
+                self.fallback.hand_mut().remove(m.index);
+                m.play
+            },
+            None => self.fallback.take_turn(state)
+        }
+    }
+}
+
+
+impl Player for HybridBot {
+    fn hand(&self) -> &Vec<Card> {
+        self.fallback.hand()
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        self.fallback.hand_mut()
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.fallback.loss_count()
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.fallback.add_losses(count);
+    }
+
+    fn rand(&mut self, rng: &mut StdRng) {
+        self.fallback.rand(rng);
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+
+////////////////
+/// Endgame ///
+//////////////
+// Search node: total, seat to move, direction, unseen pool, the solver's hand before drawing
+type EndgameKey = (i64, usize, i64, [usize; 14], [usize; 14]);
+
+// Most positions one solve may visit, past this the endgame is too open to solve while a game waits
+const ENDGAME_NODES: usize = 20000;
+
+// Expectiminimax over the unseen cards once the total is close to 99, searched until a seat busts
+// Every seat plays to minimise its own chance of busting. Unseen cards are the build_deck composition minus
+// the discard and the solver's hand; the solver draws one before each of its later turns, and an opponent
+// holds hand_size cards dealt from the unseen cards at each of its turns, since its real hand is unknown.
+// The search doesn't reshuffle: every play uses up an unseen card or one of the solver's, a seat with nothing
+// safe to play busts, and so does a seat left with no cards, so every line ends in a bust.
+// The loss chances are exact under that model. A position needing more than ENDGAME_NODES is not solved at all
+// rather than estimated, Tens can pull the total back under 90 and leave far too many lines open
+struct EndgameSolver<'a> {
+    rules: &'a Ruleset,
+    me: usize,
+    num_players: usize,
+    hand_size: usize,
+    memo: HashMap<EndgameKey, Vec<f64>>
+}
+
+// Chance that hand_size cards drawn from pool hold none of the ranks in avoid
+fn chance_without(pool: &[usize; 14], avoid: &[bool; 14], hand_size: usize) -> f64 {
+    let size: usize = pool.iter().sum();
+    let kept: usize = (0..14).filter(|&r| !avoid[r]).map(|r| pool[r]).sum();
+    let draws: usize = hand_size.min(size);
+    let mut chance: f64 = 1.0;
+    for i in 0..draws {
+        if kept < i + 1 {
+            return 0.0;
+        }
+        chance *= (kept - i) as f64 / (size - i) as f64;
+    }
+    chance
+}
+
+impl<'a> EndgameSolver<'a> {
+    fn new(state: &GameState<'a>, hand_size: usize) -> EndgameSolver<'a> {
+        EndgameSolver { rules: state.rules, me: state.seat, num_players: state.num_players, hand_size, memo: HashMap::new() }
+    }
+
+    // Every (card, declared value) a card can be played as
+    fn options(&self, rank: usize) -> Vec<Play> {
+        let card = Card::from_num_form(rank);
+        match self.rules.choices(&card) {
+            Some((high, low)) => vec![Play { card: card.clone(), declared: Some(high) }, Play { card, declared: Some(low) }],
+            None => vec![Play { card, declared: None }]
+        }
+    }
+
+    fn busted(&self, seat: usize) -> Vec<f64> {
+        let mut loss = vec![0.0; self.num_players];
+        loss[seat] = 1.0;
+        loss
+    }
+
+    // Seat to move after a play, and the new direction
+    fn after(&self, seat: usize, direction: i64, play: &Play) -> (usize, i64) {
+        let direction = if self.rules.reverses(&play.card) { -direction } else { direction };
+        ((seat as i64 + direction).rem_euclid(self.num_players as i64) as usize, direction)
+    }
+
+    // Loss chance of every seat with seat to move, None once the search outgrows ENDGAME_NODES
+    fn value(&mut self, total: i64, seat: usize, direction: i64, pool: [usize; 14], hand: [usize; 14]) -> Option<Vec<f64>> {
+        let key: EndgameKey = (total, seat, direction, pool, hand);
+        if let Some(v) = self.memo.get(&key) {
+            return Some(v.clone());
+        }
+        if self.memo.len() >= ENDGAME_NODES {
+            return None;
+        }
+        let size: usize = pool.iter().sum();
+        let v: Vec<f64> = if seat == self.me {
+            // Chance over the card drawn, then the best play from the hand
+            let draws: Vec<(Option<usize>, f64)> = if size == 0 {
+                vec![(None, 1.0)]
+            } else {
+                (0..14).filter(|&r| pool[r] > 0).map(|r| (Some(r), pool[r] as f64 / size as f64)).collect()
+            };
+            let mut loss = vec![0.0; self.num_players];
+            for (draw, chance) in draws {
+                let (mut pool, mut held) = (pool, hand);
+                if let Some(r) = draw {
+                    pool[r] -= 1;
+                    held[r] += 1;
+                }
+                let mut best: Option<Vec<f64>> = None;
+                for r in (0..14).filter(|&r| held[r] > 0) {
+                    for play in self.options(r) {
+                        let new_total = self.rules.play_number(&play, total);
+                        let v = if new_total > 99 {
+                            self.busted(seat)
+                        } else {
+                            let mut rest = held;
+                            rest[r] -= 1;
+                            let (next, dir) = self.after(seat, direction, &play);
+                            self.value(new_total, next, dir, pool, rest)?
+                        };
+                        if best.as_ref().is_none_or(|b| v[seat] < b[seat]) {
+                            best = Some(v);
+                        }
+                    }
+                }
+                let best = best.unwrap_or_else(|| self.busted(seat));
+                for i in 0..self.num_players {
+                    loss[i] += chance * best[i];
+                }
+            }
+            loss
+        } else {
+            // The opponent plays the safe option it likes best among the ranks it happens to hold
+            let mut ranked: Vec<(usize, Vec<f64>)> = Vec::new();
+            for r in (0..14).filter(|&r| pool[r] > 0) {
+                for play in self.options(r) {
+                    let new_total = self.rules.play_number(&play, total);
+                    if new_total <= 99 {
+                        let mut rest = pool;
+                        rest[r] -= 1;
+                        let (next, dir) = self.after(seat, direction, &play);
+                        ranked.push((r, self.value(new_total, next, dir, rest, hand)?));
+                    }
+                }
+            }
+            ranked.sort_by(|a, b| a.1[seat].partial_cmp(&b.1[seat]).unwrap());
+            let mut loss = vec![0.0; self.num_players];
+            let mut seen = [false; 14];
+            let mut without_seen: f64 = 1.0;
+            for (r, v) in ranked {
+                if seen[r] {
+                    continue;
+                }
+                seen[r] = true;
+                let without_next = chance_without(&pool, &seen, self.hand_size);
+                for i in 0..self.num_players {
+                    loss[i] += (without_seen - without_next) * v[i];
+                }
+                without_seen = without_next;
+            }
+            loss[seat] += without_seen;
+            loss
+        };
+        self.memo.insert(key, v.clone());
+        Some(v)
+    }
+
+    // The play with the lowest chance of this seat busting, and that chance, None if the position is too open to solve
+    fn solve(&mut self, state: &GameState, hand: &[Card]) -> Option<(Move, f64)> {
+        let pool = unseen_cards(hand, state.counts);
+        let held = composition(hand);
+        let mut best: Option<(Move, f64)> = None;
+        for m in state.legal_moves(hand) {
+            let loss: f64 = if m.busts {
+                1.0
+            } else {
+                let mut rest = held;
+                rest[m.play.card.num_form() as usize] -= 1;
+                let (next, dir) = self.after(state.seat, state.direction, &m.play);
+                self.value(m.total, next, dir, pool, rest)?[self.me]
+            };
+            if best.as_ref().is_none_or(|b| loss < b.1) {
+                best = Some((m, loss));
+            }
+        }
+        best
+    }
+}
+
+
+////////////////
+/// Game Log ///
+//////////////
+// Step through a saved log, one position per Enter, q quits
+fn replay(path: &str) -> io::Result<()> {
+    let log = GameLog::read_jsonl(path)?;
+    let mut game: usize = 0;
+    for event in log.events.iter() {
+        match event {
+            GameEvent::Start { players, first, rules } => {
+                game += 1;
+                println!("=== Game {} : {} players, seat {} starts, {} rules ===", game, players, first, rules);
+                continue;
+            },
+            GameEvent::Deal { seat, hand } => {
+                println!("Seat {} dealt {}", seat, hand_to_json(hand));
+                continue;
+            },
+            GameEvent::Play { seat, hand, card, declared, total, direction } => {
+                let declared = match declared {
+                    Some(x) => format!(" as {}", x),
+                    None => String::new()
+                };
+                println!("Seat {} holds {} and plays {}{}, total is now {}, direction {}", seat, hand_to_json(hand), card, declared, total, direction);
+            },
+            GameEvent::Reverse { direction } => println!("Play reverses, direction is now {}", direction),
+            GameEvent::Reshuffle { deck } => println!("Discard reshuffled into a deck of {}", deck),
+            GameEvent::Bust { seat, total } => println!("Seat {} busts at {}", seat, total)
+        }
+        let mut read = String::new();
+        io::stdin().read_line(&mut read)?;
+        if read.trim() == "q" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+
+////////////////
+/// Explain ///
+//////////////
+// Why BotNod played what it did on one turn: what it saw, how it scored every rank, and which inputs drove the choice
+struct Explanation {
+    inputs: Vec<f64>,
+    scores: Vec<f64>,
+    // Index in hand of the card played
+    chosen: usize,
+    // Ranks scored above the played card but not held
+    skipped: Vec<usize>,
+    // Per input, the change in margin when the input is zeroed, the margin's slope, and whether zeroing changes the card
+    attribution: Vec<(f64, f64, bool)>
+}
+
+// The held rank the scores favour and its lead over the next best held rank, the whole score when only one rank is held
+fn held_margin(scores: &[f64], hand: &[Card]) -> (usize, f64) {
+    let mut ranks: Vec<usize> = hand.iter().map(|c| c.num_form() as usize).collect();
+    ranks.sort();
+    ranks.dedup();
+    ranks.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap());
+    let lead = match ranks.get(1) {
+        Some(&next) => scores[ranks[0]] - scores[next],
+        None => scores[ranks[0]]
+    };
+    (ranks[0], lead)
+}
 
-trait Turn {
-    fn take_turn(&mut self, current_number: i64, discard: HashMap<Card, usize>, direction: i64, num_players: usize) -> Card;
+// Attribution is by perturbation, since Nod only gives gradients with respect to weights:
+// each input is zeroed, and nudged either way by step, and the lead of the played rank over the rest of the hand is measured again
+fn explain(bot: &mut BotNod, state: &GameState, step: f64) -> Explanation {
+    let inputs: Vec<f64> = bot.encoder.encode(&bot.hand, state);
+    let mut scores: Vec<f64> = bot.brain.process(inputs.clone());
+    scores.truncate(14);
+    let (chosen, _) = bot.choose(state);
+    let played = bot.hand[chosen].num_form() as usize;
+    let skipped: Vec<usize> = (0..scores.len()).filter(|&r| scores[r] > scores[played] && !bot.hand.iter().any(|c| c.num_form() as usize == r)).collect();
+    let (_, margin) = held_margin(&scores, &bot.hand);
+    let mut attribution: Vec<(f64, f64, bool)> = Vec::new();
+    for i in 0..inputs.len() {
+        let mut perturbed = |value: f64| {
+            let mut changed = inputs.clone();
+            changed[i] = value;
+            held_margin(&bot.brain.process(changed), &bot.hand)
+        };
+        let (rank, zeroed) = perturbed(0.0);
+        let (_, up) = perturbed(inputs[i] + step);
+        let (_, down) = perturbed(inputs[i] - step);
+        attribution.push((margin - zeroed, (up - down) / (2.0 * step), rank != played));
+    }
+    Explanation { inputs, scores, chosen, skipped, attribution }
 }
 
-// Define the real person player
-#[derive(Default)]
-struct Person {
-    hand: Vec<Card>,
-    losses: u32
-}
-
-///// Person methods /////
-
-impl Person {
-    fn play_card(&mut self) -> Card{
-        let mut choose: bool = true;
-        let mut selection: usize = 1;
-        while choose {
-            let mut read = String::new();
-            io::stdin().read_line(&mut read).expect("Invalid input");
-            let input = read.trim();
-            let see = vec![1,2,3];
-            selection = match input{
-                "1" => 1,
-                "2" => 2,
-                "3" => 3,
-                _ => 4
-            };
-            if see.contains(&selection) {
-                choose = false;
+fn print_explanation(bot: &BotNod, state: &GameState, explanation: &Explanation, top: usize) {
+    let names = bot.encoder.names();
+    let hand: Vec<String> = bot.hand.iter().map(|c| c.to_string()).collect();
+    println!("Hand {}, total {}, direction {}, {} players, {} encoder", hand.join(" "), state.total, state.direction, state.num_players, bot.encoder.tag());
+    println!("Inputs:");
+    for (name, value) in names.iter().zip(explanation.inputs.iter()) {
+        println!("  {:<18} {:>8.4}", name, value);
+    }
+    println!("Card scores:");
+    let played = bot.hand[explanation.chosen].num_form() as usize;
+    for (rank, score) in explanation.scores.iter().enumerate() {
+        let note = if rank == played {
+            "played"
+        } else if explanation.skipped.contains(&rank) {
+            "skipped, not in hand"
+        } else if bot.hand.iter().any(|c| c.num_form() as usize == rank) {
+            "in hand"
+        } else {
+            ""
+        };
+        println!("  {:<8} {:>8.4}  {}", Card::from_num_form(rank).to_string(), score, note);
+    }
+    println!("Inputs that drove the choice, by the change in lead when zeroed:");
+    let mut order: Vec<usize> = (0..explanation.attribution.len()).collect();
+    order.sort_by(|&a, &b| explanation.attribution[b].0.abs().partial_cmp(&explanation.attribution[a].0.abs()).unwrap());
+    for &i in order.iter().take(top) {
+        let (zeroed, slope, flips) = explanation.attribution[i];
+        println!("  {:<18} {:>+8.4}  slope {:>+8.4}{}", names.get(i).map(|n| n.as_str()).unwrap_or("?"), zeroed, slope,
+            if flips { "  zeroing it changes the card" } else { "" });
+    }
+}
+
+// nod-99 explain <model> <cards> <total> [key=value ...], cards are comma separated names such as Ace,Ten,King
+// Keys: direction (1 or -1), players, discards (comma separated cards since the last reshuffle), deck, rules, top
+fn explain_command(args: &[String]) -> Result<(), String> {
+    let usage = "explain needs a model, a hand of comma separated cards and a total";
+    let model = args.first().ok_or(usage)?;
+    let cards = |text: &str| text.split(',').filter(|x| !x.is_empty()).map(|x| Card::from_name(x.trim()).ok_or(format!("{} is not a card", x)))
+        .collect::<Result<Vec<Card>, String>>();
+    let hand = cards(args.get(1).ok_or(usage)?)?;
+    if hand.is_empty() {
+        return Err(String::from(usage));
+    }
+    let total = args.get(2).and_then(|x| x.parse::<i64>().ok()).ok_or(usage)?;
+    let mut direction: i64 = 1;
+    let mut num_players: usize = 2;
+    let mut discards: Vec<Card> = Vec::new();
+    let mut deck: Option<usize> = None;
+    let mut rules = Ruleset::standard();
+    let mut top: usize = 10;
+    for arg in args.iter().skip(3) {
+        let (key, value) = arg.split_once('=').ok_or(format!("{} is not a key=value setting", arg))?;
+        let number = || value.parse::<usize>().map_err(|_| format!("{} needs a whole number", key));
+        match key {
+            "direction" => direction = if value == "-1" { -1 } else { 1 },
+            "players" => num_players = number()?.max(2),
+            "discards" => discards = cards(value)?,
+            "deck" => deck = Some(number()?),
+            "rules" => rules = Ruleset::by_name(value).ok_or(format!("unknown ruleset {}", value))?,
+            "top" => top = number()?,
+            _ => return Err(format!("unknown setting {}", key))
+        }
+    }
+    let (brain, header) = load_model(model, "tanh", "sigmoid")?;
+    let counts = composition(&discards);
+    let history: Vec<Play> = Vec::new();
+    let deck_size = deck.unwrap_or(build_deck().len().saturating_sub(discards.len() + 3 * num_players));
+    let state = GameState { total, direction, seat: 0, num_players, counts: &counts, history: &history, deck_size, rules: &rules };
+    let mut bot = BotNod { hand, losses: 0, brain, encoder: header.encoder };
+    let explanation = explain(&mut bot, &state, 0.01);
+    print_explanation(&bot, &state, &explanation, top);
+    Ok(())
+}
+
+
+/////////////////
+/// Protocol ///
+///////////////
+// Line protocol for bots running as separate programs, every message is one line of space separated words
+// Engine to bot:
+//   nod99 1                      handshake with the protocol version, the bot answers ready with an optional name
+//   turn total <t> direction <d> seat <s> players <n> deck <k> rules <name> counts <14 numbers> hand <cards>
+//                                counts are discards since the last reshuffle indexed by Card::num_form,
+//                                cards are written as in Display (Ace, Two, ... King, OneEye)
+//   quit                         the bot should exit
+// Bot to engine:
+//   ready [name]                 reply to the handshake, lines before it such as a banner are skipped
+//   play <index> [value]         index into the hand as sent, value is the declared value for cards with a choice
+//                                and is ignored for the rest, leaving it out declares the rules default
+//
+// Timeouts, dead processes and unreadable or illegal replies are faults, a fault plays the first non busting card
+// for the bot. A timed out or dead process is stopped and a fresh one started on the next turn
+// Why a turn went wrong, only lost processes need restarting
+enum Fault {
+    Illegal(String),
+    Lost(String)
+}
+
+pub struct ProcessBot {
+    hand: Vec<Card>,
+    losses: u32,
+    command: Vec<String>,
+    timeout: time::Duration,
+    faults: u32,
+    name: Option<String>,
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    replies: Option<mpsc::Receiver<String>>
+}
+
+impl ProcessBot {
+    // command[0] is the program, the rest its arguments, nothing is started until the first turn
+    pub fn new(command: Vec<String>, timeout: time::Duration) -> ProcessBot {
+        ProcessBot { hand: vec![], losses: 0, command, timeout, faults: 0, name: None, child: None, stdin: None, replies: None }
+    }
+
+    pub fn faults(&self) -> u32 {
+        self.faults
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        let stdin = self.stdin.as_mut().ok_or("bot is not running")?;
+        writeln!(stdin, "{}", line).and_then(|_| stdin.flush()).map_err(|e| format!("could not write to bot: {}", e))
+    }
+
+    fn receive(&mut self) -> Result<String, String> {
+        let replies = self.replies.as_ref().ok_or("bot is not running")?;
+        match replies.recv_timeout(self.timeout) {
+            Ok(line) => Ok(line),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(format!("bot took longer than {:?}", self.timeout)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(String::from("bot closed its output"))
+        }
+    }
+
+    fn connect(&mut self) -> Result<(), String> {
+        if self.child.is_some() {
+            return Ok(());
+        }
+        let program = self.command.first().ok_or("empty bot command")?;
+        let mut child = Command::new(program).args(&self.command[1..]).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()
+            .map_err(|e| format!("could not start {}: {}", program, e))?;
+        let stdout = child.stdout.take().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        // Reading on a separate thread lets receive give up after the timeout
+        std::thread::spawn(move || {
+            for line in io::BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { break; },
+                    Err(_) => break
+                }
+            }
+        });
+        self.stdin = child.stdin.take();
+        self.child = Some(child);
+        self.replies = Some(receiver);
+        self.send("nod99 1")?;
+        let mut reply = self.receive()?;
+        for _skipped in 0..100 {
+            if reply.starts_with("ready") {
+                break;
+            }
+            reply = self.receive()?;
+        }
+        let mut words = reply.split_whitespace();
+        if words.next() != Some("ready") {
+            return Err(format!("expected ready, bot said {:?}", reply));
+        }
+        let name: Vec<&str> = words.collect();
+        if !name.is_empty() {
+            self.name = Some(name.join(" "));
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        let _ = self.send("quit");
+        self.stdin = None;
+        self.replies = None;
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    // Ask the process for a move, returns the chosen play and its index in the hand
+    fn ask(&mut self, state: &GameState) -> Result<(usize, Play), Fault> {
+        self.connect().map_err(Fault::Lost)?;
+        let counts: Vec<String> = state.counts.iter().map(|c| c.to_string()).collect();
+        let hand: Vec<String> = self.hand.iter().map(|c| c.to_string()).collect();
+        let line = format!("turn total {} direction {} seat {} players {} deck {} rules {} counts {} hand {}",
+            state.total, state.direction, state.seat, state.num_players, state.deck_size, state.rules.name, counts.join(" "), hand.join(" "));
+        self.send(&line).map_err(Fault::Lost)?;
+        let reply = self.receive().map_err(Fault::Lost)?;
+        let words: Vec<&str> = reply.split_whitespace().collect();
+        if words.len() < 2 || words.len() > 3 || words[0] != "play" {
+            return Err(Fault::Illegal(format!("expected play, bot said {:?}", reply)));
+        }
+        let index = match words[1].parse::<usize>() {
+            Ok(x) if x < self.hand.len() => x,
+            _ => return Err(Fault::Illegal(format!("bot played index {} from a hand of {}", words[1], self.hand.len())))
+        };
+        let card = self.hand[index].clone();
+        let declared = match (state.rules.choices(&card), words.get(2)) {
+            (None, _) => None,
+            (Some(_), None) => state.rules.default_value(&card, state.total),
+            (Some((high, low)), Some(word)) => match word.parse::<i64>() {
+                Ok(x) if x == high || x == low => Some(x),
+                _ => return Err(Fault::Illegal(format!("bot declared {} for {}, which can only be {} or {}", word, card, high, low)))
+            }
+        };
+        Ok((index, Play { card, declared }))
+    }
+
+    fn play_card(&mut self, state: &GameState) -> Play {
+        match self.ask(state) {
+            Ok((index, play)) => {
+                self.hand.remove(index);
+                play
+            },
+            Err(fault) => {
+                self.faults += 1;
+                let name = self.name.clone().unwrap_or(self.command.join(" "));
+                match fault {
+                    Fault::Illegal(reason) => eprintln!("Bot {} fault: {}", name, reason),
+                    Fault::Lost(reason) => {
+                        eprintln!("Bot {} fault: {}, restarting it", name, reason);
+                        self.disconnect();
+                    }
+                }
+                let moves = state.legal_moves(&self.hand);
+                let m = moves.iter().find(|m| !m.busts).unwrap_or(&moves[0]).clone();
+                self.hand.remove(m.index);
+                m.play
+            }
+        }
+    }
+}
+
+impl Drop for ProcessBot {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+impl Player for ProcessBot {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.losses
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+// Each copy runs its own process
+impl Clone for ProcessBot {
+    fn clone(&self) -> ProcessBot {
+        let mut copy = ProcessBot::new(self.command.clone(), self.timeout);
+        copy.hand = self.hand.clone();
+        copy.losses = self.losses;
+        copy.faults = self.faults;
+        copy
+    }
+}
+
+// Reference bot for the protocol, run with nod-99 refbot
+// Plays the first card that keeps the total at or under 99 and declares the rules default
+fn reference_bot() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"nod99") => writeln!(stdout, "ready refbot")?,
+            Some(&"turn") => {
+                let field = |key: &str| words.iter().position(|w| *w == key).map(|i| i + 1);
+                let total: i64 = field("total").and_then(|i| words.get(i)).and_then(|w| w.parse().ok()).unwrap_or(0);
+                let rules = field("rules").and_then(|i| words.get(i)).and_then(|w| Ruleset::by_name(w)).unwrap_or(Ruleset::standard());
+                let hand: Vec<Card> = field("hand").map(|i| words[i..].iter().filter_map(|w| Card::from_name(w)).collect()).unwrap_or(vec![]);
+                let index = hand.iter().position(|c| rules.new_number(c, total) <= 99).unwrap_or(0);
+                match hand.get(index).and_then(|c| rules.default_value(c, total)) {
+                    Some(value) => writeln!(stdout, "play {} {}", index, value)?,
+                    None => writeln!(stdout, "play {}", index)?
+                }
+            },
+            Some(&"quit") => break,
+            _ => ()
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+
+///////////////
+/// Server ///
+/////////////
+// Seconds a person at a networked table has to answer before the server plays for them
+const TURN_SECONDS: u64 = 60;
+
+// A person at a networked table, they are sent their own hand and the public state and answer with plain lines
+// If the turn takes longer than the turn timeout, however much they type, the server plays the first non busting
+// card for them that turn, if the connection drops it does so for the rest of the games
+pub struct RemotePerson {
+    hand: Vec<Card>,
+    losses: u32,
+    seat: usize,
+    stream: TcpStream,
+    reader: io::BufReader<TcpStream>,
+    turn_timeout: time::Duration,
+    connected: bool,
+    // Plays of the current game already shown to them
+    seen: usize
+}
+
+impl RemotePerson {
+    fn new(stream: TcpStream, seat: usize, turn_timeout: time::Duration) -> io::Result<RemotePerson> {
+        let reader = io::BufReader::new(stream.try_clone()?);
+        Ok(RemotePerson { hand: vec![], losses: 0, seat, stream, reader, turn_timeout, connected: true, seen: 0 })
+    }
+
+    // Send a line, a failed write marks them as gone
+    fn tell(&mut self, line: &str) {
+        if self.connected && writeln!(self.stream, "{}", line).is_err() {
+            self.drop_out();
+        }
+    }
+
+    fn drop_out(&mut self) {
+        if self.connected {
+            eprintln!("Seat {} disconnected, playing for them", self.seat);
+        }
+        self.connected = false;
+    }
+
+    // Read one trimmed line, None when the turn is past its deadline or they have gone
+    fn listen(&mut self, deadline: time::Instant) -> Option<String> {
+        if !self.connected {
+            return None;
+        }
+        let left = deadline.saturating_duration_since(time::Instant::now());
+        if left.is_zero() {
+            self.tell("Out of time, the server played for you");
+            return None;
+        }
+        if self.stream.set_read_timeout(Some(left)).is_err() {
+            self.drop_out();
+            return None;
+        }
+        let mut read = String::new();
+        match self.reader.read_line(&mut read) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                self.tell("Out of time, the server played for you");
+                None
+            },
+            Ok(0) | Err(_) => {
+                self.drop_out();
+                None
+            },
+            Ok(_) => Some(read.trim().to_string())
+        }
+    }
+
+    fn play_card(&mut self, state: &GameState) -> Play {
+        let played: Vec<String> = state.history[self.seen.min(state.history.len())..].iter().map(|p| match p.declared {
+            Some(x) => format!("{} as {}", p.card, x),
+            None => p.card.to_string()
+        }).collect();
+        self.seen = state.history.len() + 1;
+        if !played.is_empty() {
+            self.tell(&format!("Played since your last turn: {}", played.join(", ")));
+        }
+        self.tell(&format!("Your turn at seat {} of {}, the current number is {}, {} cards left in the deck",
+            state.seat, state.num_players, state.total, state.deck_size));
+        for line in move_lines(state, &self.hand) {
+            self.tell(&line);
+        }
+        let moves = state.legal_moves(&self.hand);
+        // One deadline for the whole turn, so lines that aren't answers can't hold up the table
+        let deadline = time::Instant::now() + self.turn_timeout;
+        loop {
+            self.tell(&format!("Type 1 to {} to pick a card", self.hand.len()));
+            let index = match self.listen(deadline) {
+                Some(input) => match input.parse::<usize>() {
+                    Ok(x) if x >= 1 && x <= self.hand.len() => x - 1,
+                    _ => continue
+                },
+                None => moves.iter().find(|m| !m.busts).unwrap_or(&moves[0]).index
+            };
+            let card = self.hand.remove(index);
+            let declared = match state.rules.choices(&card) {
+                Some((high, low)) => loop {
+                    self.tell(&format!("Play {} as {} or {}?", card, high, low));
+                    match self.listen(deadline) {
+                        Some(input) => match input.parse::<i64>() {
+                            Ok(x) if x == high || x == low => break Some(x),
+                            _ => continue
+                        },
+                        None => break state.rules.default_value(&card, state.total)
+                    }
+                },
+                None => None
+            };
+            return Play { card, declared };
+        }
+    }
+}
+
+impl Player for RemotePerson {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
+
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
+    }
+
+    fn loss_count(&self) -> u32 {
+        self.losses
+    }
+
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    // A new hand means a new game
+    fn clear_hand(&mut self) {
+        self.hand.clear();
+        self.seen = 0;
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+// Copies share the connection
+impl Clone for RemotePerson {
+    fn clone(&self) -> RemotePerson {
+        let stream = self.stream.try_clone().expect("Failed to share the connection");
+        let mut copy = RemotePerson::new(stream, self.seat, self.turn_timeout).expect("Failed to share the connection");
+        copy.hand = self.hand.clone();
+        copy.losses = self.losses;
+        copy.connected = self.connected;
+        copy
+    }
+}
+
+// The bots filling the empty seats of a table, BotNod when a model is given, otherwise the basic bot
+fn table_bots(count: usize, model: Option<String>) -> io::Result<Vec<Box<dyn Player>>> {
+    let model = match &model {
+        Some(name) => Some(load_model(name, "tanh", "sigmoid").map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+        None => None
+    };
+    let mut bots: Vec<Box<dyn Player>> = Vec::new();
+    for _i in 0..count {
+        let bot: Box<dyn Player> = match &model {
+            Some((brain, header)) => Box::new(BotNod { hand: vec![], losses: 0, brain: brain.clone(), encoder: header.encoder }),
+            None => Box::new(BasicBot::reference())
+        };
+        bots.push(bot);
+    }
+    Ok(bots)
+}
+
+// Host games on a bound listener, waiting for humans to join before seating them ahead of the bots
+fn serve(listener: TcpListener, humans: usize, mut bots: Vec<Box<dyn Player>>, games: usize, turn_timeout: time::Duration, rules: &Ruleset, rng: &mut StdRng) -> io::Result<()> {
+    let seats = humans + bots.len();
+    if seats < 2 || seats > rules.max_seats() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("a table has 2 to {} seats, not {}", rules.max_seats(), seats)));
+    }
+    println!("Waiting for {} players on {}", humans, listener.local_addr()?);
+    let mut people: Vec<RemotePerson> = Vec::new();
+    while people.len() < humans {
+        let (stream, address) = listener.accept()?;
+        let mut person = RemotePerson::new(stream, people.len(), turn_timeout)?;
+        println!("Seat {} joined from {}", people.len(), address);
+        person.tell(&format!("Welcome to 99, you are seat {} of {}", people.len(), seats));
+        people.push(person);
+    }
+    for game in 0..games {
+        let first = game % seats;
+        for person in people.iter_mut() {
+            person.tell(&format!("=== Game {} of {}, seat {} starts ===", game + 1, games, first));
+        }
+        let mut players: Vec<&mut dyn Player> = people.iter_mut().map(|p| p as &mut dyn Player).collect();
+        players.extend(bots.iter_mut().map(|b| &mut **b as &mut dyn Player));
+        let loser = run_game(&mut players, first as i64, rules, rng, None);
+        let losses: Vec<String> = players.iter().map(|p| p.loss_count().to_string()).collect();
+        let line = format!("Seat {} busts, losses by seat: {}", loser, losses.join(" "));
+        println!("{}", line);
+        for person in people.iter_mut() {
+            person.tell(&line);
+        }
+    }
+    Ok(())
+}
+
+// Connect a terminal to a server, lines typed go to the table and everything the table says is printed
+fn join(port: u16) -> io::Result<()> {
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let mut incoming = stream.try_clone()?;
+    std::thread::spawn(move || {
+        let _ = io::copy(&mut incoming, &mut io::stdout());
+        println!("The server closed the table");
+        std::process::exit(0);
+    });
+    let mut outgoing = stream;
+    io::copy(&mut io::stdin(), &mut outgoing)?;
+    Ok(())
+}
+
+
+
+
+////////////////
+/// Ratings ///
+//////////////
+// Elo scale: a 400 point gap is 10 to 1 odds, the basic bot anchors the scale at 1500
+const ANCHOR_RATING: f64 = 1500.0;
+
+fn elo_expected(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0))
+}
+
+// The rating whose expected score against these (opponent rating, wins, games) records equals the wins achieved
+// Perfect and zero scores are pulled in by half a game so the rating stays finite
+fn performance_rating(records: &[(f64, f64, usize)]) -> f64 {
+    let games: f64 = records.iter().map(|r| r.2 as f64).sum();
+    if games == 0.0 {
+        return ANCHOR_RATING;
+    }
+    let wins: f64 = records.iter().map(|r| r.1).sum::<f64>().clamp(0.5, games - 0.5);
+    let mut low: f64 = -2000.0;
+    let mut high: f64 = 5000.0;
+    for _ in 0..60 {
+        let mid = (low + high) / 2.0;
+        let expected: f64 = records.iter().map(|r| elo_expected(mid, r.0) * r.2 as f64).sum();
+        if expected < wins {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+// Rating of each agent of a generation from its losses against opponents all taken to be at the anchor
+fn generation_ratings(estimates: &[LossEstimate]) -> Vec<f64> {
+    estimates.iter().map(|e| performance_rating(&[(ANCHOR_RATING, e.games.saturating_sub(e.losses) as f64, e.games)])).collect()
+}
+
+// A past champion, saved as name.nod and rated when it was inducted
+// The seed names the run it came from, halls written before it was kept have none
+struct HallMember {
+    name: String,
+    generation: usize,
+    rating: f64,
+    seed: Option<u64>
+}
+
+// Champions of past generations with fixed ratings, every newcomer is rated against all of them and the basic bot,
+// so a rise in rating means beating earlier champions and not only the opponents the population trained against
+struct HallOfFame {
+    path: String,
+    members: Vec<HallMember>
+}
+
+impl HallOfFame {
+    // A missing file is an empty hall
+    fn read(path: &str) -> io::Result<HallOfFame> {
+        let mut hall = HallOfFame { path: path.to_string(), members: vec![] };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(hall),
+            Err(e) => return Err(e)
+        };
+        for (number, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let member = match (words.first(), words.get(1).and_then(|w| w.parse::<usize>().ok()), words.get(2).and_then(|w| w.parse::<f64>().ok())) {
+                (Some(name), Some(generation), Some(rating)) => {
+                    let seed = match words.get(3) {
+                        Some(w) => Some(w.parse::<u64>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{} has a bad seed", path, number + 1)))?),
+                        None => None
+                    };
+                    HallMember { name: name.to_string(), generation, rating, seed }
+                },
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}:{} is not a hall of fame line", path, number + 1)))
+            };
+            hall.members.push(member);
+        }
+        Ok(hall)
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let lines: Vec<String> = self.members.iter().map(|m| match m.seed {
+            Some(seed) => format!("{} {} {} {}\n", m.name, m.generation, m.rating, seed),
+            None => format!("{} {} {}\n", m.name, m.generation, m.rating)
+        }).collect();
+        std::fs::write(&self.path, lines.concat())
+    }
+
+    // Rate the champion of a generation against the basic bot and every member, then save it and add it to the hall
+    // Members are known by the seed of their run and their generation, so a generation already in the hall from the
+    // same run, as after resuming, keeps its first rating while other runs sharing the hall add their own champions
+    // Members play with the encoder they were saved with, so a hall can outlive a change of encoder
+    fn induct(&mut self, champion: Network, generation: usize, experiment: &Experiment, tokens: u32, rules: &Ruleset, rng: &mut StdRng) -> Result<f64, String> {
+        let seed: u64 = experiment.seed.ok_or("the run has no seed to name its champions by")?;
+        if let Some(member) = self.members.iter().find(|m| m.seed == Some(seed) && m.generation == generation) {
+            return Ok(member.rating);
+        }
+        let mut ratings: Vec<f64> = vec![ANCHOR_RATING];
+        let mut opponents: Vec<Box<dyn Player>> = vec![Box::new(BasicBot::reference())];
+        for member in self.members.iter() {
+            let (brain, header) = load_model(&member.name, &experiment.hidden, &experiment.output)?;
+            opponents.push(Box::new(BotNod { hand: vec![], losses: 0, brain, encoder: header.encoder }));
+            ratings.push(member.rating);
+        }
+        let games = experiment.hall_games;
+        let name = format!("{}_hof_{}_{}", experiment.model_out, seed, generation);
+        save_model(&champion, &name, &experiment.model_header("genetic", vec![("generation", generation.to_string())])).map_err(|e| format!("could not save {}: {}", name, e))?;
+        let contender: Box<dyn Player> = Box::new(BotNod { hand: vec![], losses: 0, brain: champion, encoder: experiment.encoder() });
+        // Each opponent's losses are the champion's wins against it
+        let results = run_team_round_robin_1v1(opponents, vec![contender], games, tokens, rules, rng);
+        let records: Vec<(f64, f64, usize)> = ratings.iter().zip(results[0].iter()).map(|(&r, &w)| (r, w as f64, games)).collect();
+        let rating = performance_rating(&records);
+        self.members.push(HallMember { name, generation, rating, seed: Some(seed) });
+        Ok(rating)
+    }
+
+    // Ratings of the champions in the order they were inducted, with the change from the one before
+    fn report(&self) {
+        let mut previous: Option<f64> = None;
+        for member in self.members.iter() {
+            let change = match previous {
+                Some(x) => format!("{:+.0}", member.rating - x),
+                None => String::from("-")
+            };
+            println!("Generation {:>5}  rating {:>6.0}  change {:>5}  {}", member.generation, member.rating, change, member.name);
+            previous = Some(member.rating);
+        }
+    }
+}
+
+
+////////////////
+/// Metrics ///
+//////////////
+// What one generation of training looked like, one row of a run's metrics file
+// Loss rates are across the whole population, the elite are the survivors by selection score
+struct GenerationMetrics {
+    generation: usize,
+    games_played: usize,
+    eval_seconds: f64,
+    loss_min: f64,
+    loss_q1: f64,
+    loss_median: f64,
+    loss_q3: f64,
+    loss_max: f64,
+    loss_mean: f64,
+    elite_loss: f64,
+    top5_loss: f64,
+    best_score: f64,
+    elite_cutoff_score: f64,
+    best_rating: f64,
+    // Filled in by the caller, files from before diversity was measured read them as 0
+    weight_diversity: f64,
+    behaviour_diversity: f64,
+    species: usize,
+    genetics: GeneticParams
+}
+
+impl GenerationMetrics {
+    fn measure(generation: usize, games_played: usize, eval_seconds: f64, estimates: &[LossEstimate], scores: &[f64], genetics: &GeneticParams) -> GenerationMetrics {
+        let mut rates: Vec<f64> = estimates.iter().map(|e| e.rate()).collect();
+        rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let quantile = |q: f64| if rates.is_empty() { 0.0 } else { rates[((rates.len() - 1) as f64 * q).round() as usize] };
+        let mut ranked: Vec<f64> = scores.to_vec();
+        ranked.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let elite = genetics.survivors.clamp(1, ranked.len().max(1));
+        let ratings = generation_ratings(estimates);
+        GenerationMetrics {
+            generation,
+            games_played,
+            eval_seconds,
+            loss_min: quantile(0.0),
+            loss_q1: quantile(0.25),
+            loss_median: quantile(0.5),
+            loss_q3: quantile(0.75),
+            loss_max: quantile(1.0),
+            loss_mean: if rates.is_empty() { 0.0 } else { rates.iter().sum::<f64>() / rates.len() as f64 },
+            elite_loss: top_loss_rate(estimates, scores, elite),
+            top5_loss: top_loss_rate(estimates, scores, 5),
+            best_score: ranked.first().cloned().unwrap_or(0.0),
+            elite_cutoff_score: ranked.get(elite - 1).cloned().unwrap_or(0.0),
+            best_rating: ratings.iter().cloned().fold(f64::MIN, f64::max),
+            weight_diversity: 0.0,
+            behaviour_diversity: 0.0,
+            species: 0,
+            genetics: genetics.clone()
+        }
+    }
+
+    // Column names and values, in file order
+    // New columns go at the end, so rows appended to a file from an older run still line up under its header
+    fn fields(&self) -> Vec<(&'static str, f64)> {
+        let g = &self.genetics;
+        vec![
+            ("generation", self.generation as f64), ("games_played", self.games_played as f64), ("eval_seconds", self.eval_seconds),
+            ("loss_min", self.loss_min), ("loss_q1", self.loss_q1), ("loss_median", self.loss_median), ("loss_q3", self.loss_q3),
+            ("loss_max", self.loss_max), ("loss_mean", self.loss_mean), ("elite_loss", self.elite_loss), ("top5_loss", self.top5_loss),
+            ("best_score", self.best_score), ("elite_cutoff_score", self.elite_cutoff_score), ("best_rating", self.best_rating),
+            ("survivors", g.survivors as f64), ("parents", g.parents as f64), ("crossovers", g.crossovers as f64), ("mutants", g.mutants as f64),
+            ("mutation_passes", g.mutation_passes as f64), ("mutation_rate", g.mutation_rate), ("mutation_size", g.mutation_size),
+            ("weight_diversity", self.weight_diversity), ("behaviour_diversity", self.behaviour_diversity), ("species", self.species as f64)
+        ]
+    }
+
+    fn from_fields(fields: &HashMap<String, f64>) -> Option<GenerationMetrics> {
+        let get = |name: &str| fields.get(name).cloned();
+        Some(GenerationMetrics {
+            generation: get("generation")? as usize,
+            games_played: get("games_played")? as usize,
+            eval_seconds: get("eval_seconds")?,
+            loss_min: get("loss_min")?,
+            loss_q1: get("loss_q1")?,
+            loss_median: get("loss_median")?,
+            loss_q3: get("loss_q3")?,
+            loss_max: get("loss_max")?,
+            loss_mean: get("loss_mean")?,
+            elite_loss: get("elite_loss")?,
+            top5_loss: get("top5_loss")?,
+            best_score: get("best_score")?,
+            elite_cutoff_score: get("elite_cutoff_score")?,
+            best_rating: get("best_rating")?,
+            weight_diversity: get("weight_diversity").unwrap_or(0.0),
+            behaviour_diversity: get("behaviour_diversity").unwrap_or(0.0),
+            species: get("species").unwrap_or(0.0) as usize,
+            genetics: GeneticParams { survivors: get("survivors")? as usize, parents: get("parents")? as usize, crossovers: get("crossovers")? as usize,
+                mutants: get("mutants")? as usize, mutation_passes: get("mutation_passes")? as usize, mutation_rate: get("mutation_rate")?,
+                mutation_size: get("mutation_size")? }
+        })
+    }
+
+    // Append to path, as JSON lines when it ends in .jsonl and as CSV with a header otherwise
+    fn append(&self, path: &str) -> io::Result<()> {
+        let fields = self.fields();
+        let line = if path.ends_with(".jsonl") {
+            let pairs: Vec<String> = fields.iter().map(|(name, value)| format!("\"{}\":{}", name, value)).collect();
+            format!("{{{}}}", pairs.join(","))
+        } else {
+            fields.iter().map(|(_, value)| value.to_string()).collect::<Vec<String>>().join(",")
+        };
+        let fresh = std::fs::metadata(path).map(|m| m.len() == 0).unwrap_or(true);
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        if fresh && !path.ends_with(".jsonl") {
+            writeln!(file, "{}", fields.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(","))?;
+        }
+        writeln!(file, "{}", line)
+    }
+}
+
+// Read a metrics file written by append, a generation written twice, as after resuming, keeps its last row
+fn read_metrics(path: &str) -> io::Result<Vec<GenerationMetrics>> {
+    let text = std::fs::read_to_string(path)?;
+    let bad = |line: usize| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{} is not a metrics row", path, line + 1));
+    let mut rows: Vec<GenerationMetrics> = Vec::new();
+    let mut header: Vec<String> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields: HashMap<String, f64> = HashMap::new();
+        if path.ends_with(".jsonl") {
+            for (name, value) in parse_json_line(line).ok_or(bad(number))? {
+                fields.insert(name, real_from_json(Some(&value)).ok_or(bad(number))?);
+            }
+        } else if header.is_empty() {
+            header = line.split(',').map(|x| x.trim().to_string()).collect();
+            continue;
+        } else {
+            for (name, value) in header.iter().zip(line.split(',')) {
+                fields.insert(name.clone(), value.trim().parse::<f64>().map_err(|_| bad(number))?);
+            }
+        }
+        let row = GenerationMetrics::from_fields(&fields).ok_or(bad(number))?;
+        rows.retain(|r| r.generation != row.generation);
+        rows.push(row);
+    }
+    rows.sort_by_key(|r| r.generation);
+    Ok(rows)
+}
+
+// Side by side report of one or more runs' metrics files
+fn summary(paths: &[String]) -> io::Result<()> {
+    println!("{:<28} {:>5} {:>12} {:>10} {:>10} {:>10} {:>16} {:>11} {:>8} {:>9}", "run", "gens", "games", "eval s", "first top5", "final top5",
+        "best top5 (gen)", "final median", "rating", "behaviour");
+    for path in paths {
+        let rows = read_metrics(path)?;
+        let (first, last) = match (rows.first(), rows.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                println!("{:<28} no generations recorded", path);
+                continue;
+            }
+        };
+        let best = rows.iter().min_by(|a, b| a.top5_loss.partial_cmp(&b.top5_loss).unwrap()).unwrap();
+        let eval: f64 = rows.iter().map(|r| r.eval_seconds).sum();
+        println!("{:<28} {:>5} {:>12} {:>10.1} {:>10.4} {:>10.4} {:>9.4} ({:>4}) {:>11.4} {:>8.0} {:>9.3}", path, rows.len(), last.games_played, eval,
+            first.top5_loss, last.top5_loss, best.top5_loss, best.generation, last.loss_median, last.best_rating, last.behaviour_diversity);
+    }
+    Ok(())
+}
+
+
+//////////////////
+/// Diversity ///
+////////////////
+// A fixed game position for comparing how agents play, built into a GameState when asked
+struct Probe {
+    hand: Vec<Card>,
+    total: i64,
+    direction: i64,
+    num_players: usize,
+    counts: [usize; 14],
+    deck_size: usize
+}
+
+// Probe positions drawn from their own seed, so every generation and every run is compared on the same set
+fn build_probes(count: usize, seed: u64) -> Vec<Probe> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut probes: Vec<Probe> = Vec::new();
+    for _i in 0..count {
+        let mut deck = build_deck();
+        deck.shuffle(&mut rng);
+        let hand: Vec<Card> = deck.split_off(deck.len() - 3);
+        let discards: usize = rng.gen_range(0..deck.len() / 2);
+        let counts = composition(&deck[..discards]);
+        probes.push(Probe { hand, total: rng.gen_range(0..100), direction: if rng.gen_bool(0.5) { 1 } else { -1 }, num_players: rng.gen_range(2..5),
+            counts, deck_size: deck.len() - discards });
+    }
+    probes
+}
+
+// The rank each network plays on each probe
+fn behaviour_signatures(networks: Vec<Network>, encoder: &'static dyn Encoder, probes: &[Probe], rules: &Ruleset) -> Vec<Vec<usize>> {
+    let history: Vec<Play> = Vec::new();
+    networks.into_iter().map(|brain| {
+        let mut bot = BotNod { hand: vec![], losses: 0, brain, encoder };
+        probes.iter().map(|probe| {
+            let state = GameState { total: probe.total, direction: probe.direction, seat: 0, num_players: probe.num_players, counts: &probe.counts,
+                history: &history, deck_size: probe.deck_size, rules };
+            bot.hand = probe.hand.clone();
+            let (index, _) = bot.choose(&state);
+            probe.hand[index].num_form() as usize
+        }).collect()
+    }).collect()
+}
+
+// Fraction of probes on which two agents play different ranks
+fn behaviour_distance(a: &[usize], b: &[usize]) -> f64 {
+    if a.is_empty() {
+        return 0.0;
+    }
+    a.iter().zip(b.iter()).filter(|(x, y)| x != y).count() as f64 / a.len() as f64
+}
+
+// Chance that two agents drawn from the population play different ranks on a probe, averaged over the probes
+fn behaviour_diversity(signatures: &[Vec<usize>]) -> f64 {
+    let agents = signatures.len();
+    let probes = signatures.first().map_or(0, |s| s.len());
+    if agents == 0 || probes == 0 {
+        return 0.0;
+    }
+    let mut total: f64 = 0.0;
+    for p in 0..probes {
+        let mut counts = [0usize; 14];
+        for signature in signatures.iter() {
+            counts[signature[p]] += 1;
+        }
+        total += 1.0 - counts.iter().map(|&c| (c as f64 / agents as f64).powi(2)).sum::<f64>();
+    }
+    total / probes as f64
+}
+
+// Mean Euclidean distance between the weights of every pair of networks
+fn weight_diversity(weights: &[Vec<f64>]) -> f64 {
+    let mut total: f64 = 0.0;
+    let mut pairs: usize = 0;
+    for i in 0..weights.len() {
+        for j in i + 1..weights.len() {
+            total += weights[i].iter().zip(weights[j].iter()).map(|(a, b)| (a - b) * (a - b)).sum::<f64>().sqrt();
+            pairs += 1;
+        }
+    }
+    if pairs == 0 { 0.0 } else { total / pairs as f64 }
+}
+
+// Group agents whose behaviour is within threshold of a species' first member, taken in the order given
+// Returns the species of every agent
+fn speciate(signatures: &[Vec<usize>], threshold: f64) -> Vec<usize> {
+    let mut founders: Vec<usize> = Vec::new();
+    let mut species: Vec<usize> = Vec::new();
+    for (i, signature) in signatures.iter().enumerate() {
+        match founders.iter().position(|&f| behaviour_distance(&signatures[f], signature) <= threshold) {
+            Some(s) => species.push(s),
+            None => {
+                species.push(founders.len());
+                founders.push(i);
+            }
+        }
+    }
+    species
+}
+
+// Fitness sharing: each agent's fitness is divided by the size of its species, so a crowded strategy has to be
+// clearly better to keep its places among the survivors
+fn shared_fitness(fitness: &[f64], species: &[usize]) -> Vec<f64> {
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for s in species.iter() {
+        *sizes.entry(*s).or_insert(0) += 1;
+    }
+    fitness.iter().zip(species.iter()).map(|(f, s)| f / sizes[s] as f64).collect()
+}
+
+
+///////////////
+/// Config ///
+/////////////
+// A TOML value, numbers and booleans are kept as written and converted when a key is applied
+#[derive(Clone)]
+enum TomlValue {
+    Str(String),
+    Word(String),
+    List(Vec<TomlValue>)
+}
+
+// Parse one value starting at chars[*pos]: a quoted string, an array, or a bare number or boolean
+fn parse_toml_value(chars: &[char], pos: &mut usize) -> Option<TomlValue> {
+    while chars.get(*pos).is_some_and(|c| c.is_whitespace()) {
+        *pos += 1;
+    }
+    match chars.get(*pos)? {
+        '"' => {
+            *pos += 1;
+            let mut out = String::new();
+            while *chars.get(*pos)? != '"' {
+                out.push(chars[*pos]);
+                *pos += 1;
+            }
+            *pos += 1;
+            Some(TomlValue::Str(out))
+        },
+        '[' => {
+            *pos += 1;
+            let mut items: Vec<TomlValue> = Vec::new();
+            loop {
+                while chars.get(*pos).is_some_and(|c| c.is_whitespace() || *c == ',') {
+                    *pos += 1;
+                }
+                if *chars.get(*pos)? == ']' {
+                    break;
+                }
+                items.push(parse_toml_value(chars, pos)?);
+            }
+            *pos += 1;
+            Some(TomlValue::List(items))
+        },
+        _ => {
+            let start = *pos;
+            while chars.get(*pos).is_some_and(|c| c.is_alphanumeric() || *c == '-' || *c == '+' || *c == '.' || *c == '_') {
+                *pos += 1;
+            }
+            if start == *pos {
+                return None;
+            }
+            Some(TomlValue::Word(chars[start..*pos].iter().collect()))
+        }
+    }
+}
+
+// Parse the TOML used by experiment files: [section] headers, key = value lines, # comments, and arrays that may run
+// over several lines. Keys come back as section.key
+fn parse_toml(text: &str) -> Result<Vec<(String, TomlValue)>, String> {
+    let mut entries: Vec<(String, TomlValue)> = Vec::new();
+    let mut section = String::new();
+    let mut pending = String::new();
+    let mut start_line: usize = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(i) if !line[..i].contains('"') => &line[..i],
+            _ => line
+        };
+        if pending.is_empty() {
+            start_line = number + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.starts_with('[') && trimmed.ends_with(']') && !trimmed.contains('=') {
+                section = trimmed[1..trimmed.len() - 1].trim().to_string();
+                continue;
+            }
+        }
+        pending.push_str(line);
+        pending.push('\n');
+        // Keep reading until every array is closed
+        if pending.matches('[').count() > pending.matches(']').count() {
+            continue;
+        }
+        let (key, value) = pending.split_once('=').ok_or(format!("line {}: expected key = value", start_line))?;
+        let chars: Vec<char> = value.chars().collect();
+        let mut pos: usize = 0;
+        let value = parse_toml_value(&chars, &mut pos).ok_or(format!("line {}: could not read the value of {}", start_line, key.trim()))?;
+        // Only a comment may follow the value
+        let rest: String = chars[pos.min(chars.len())..].iter().collect();
+        if !rest.trim().is_empty() && !rest.trim().starts_with('#') {
+            return Err(format!("line {}: unexpected {} after the value of {}", start_line, rest.trim(), key.trim()));
+        }
+        let key = if section.is_empty() { key.trim().to_string() } else { format!("{}.{}", section, key.trim()) };
+        entries.push((key, value));
+        pending.clear();
+    }
+    if !pending.is_empty() {
+        return Err(format!("line {}: array is never closed", start_line));
+    }
+    Ok(entries)
+}
+
+fn toml_number<T: std::str::FromStr>(key: &str, value: &TomlValue) -> Result<T, String> {
+    match value {
+        TomlValue::Word(x) => x.parse::<T>().map_err(|_| format!("{} must be a number, not {}", key, x)),
+        _ => Err(format!("{} must be a number", key))
+    }
+}
+
+fn toml_flag(key: &str, value: &TomlValue) -> Result<bool, String> {
+    match value {
+        TomlValue::Word(x) if x == "true" => Ok(true),
+        TomlValue::Word(x) if x == "false" => Ok(false),
+        _ => Err(format!("{} must be true or false", key))
+    }
+}
+
+// Strings may be left unquoted, which is handy for overrides on the command line
+fn toml_text(key: &str, value: &TomlValue) -> Result<String, String> {
+    match value {
+        TomlValue::Str(x) | TomlValue::Word(x) => Ok(x.clone()),
+        _ => Err(format!("{} must be a string", key))
+    }
+}
+
+fn toml_list(key: &str, value: &TomlValue) -> Result<Vec<TomlValue>, String> {
+    match value {
+        TomlValue::List(items) => Ok(items.clone()),
+        _ => Err(format!("{} must be an array", key))
+    }
+}
+
+// One training run: where the population comes from, who it is measured against, how often, and where results go
+// Every field has a key of the form section.key in the experiment file, defaults reproduce the original run
+#[derive(Clone)]
+struct Experiment {
+    rules: String,
+    // Drawn and recorded when missing
+    seed: Option<u64>,
+    generations: usize,
+    checkpoint_every: usize,
+    report_every: usize,
+    // A saved model prefix, or a fresh model of this shape when shape is not empty
+    source: String,
+    shape: Vec<usize>,
+    hidden: String,
+    output: String,
+    // Tag of the input encoder, see encoder_by_tag
+    encoder: String,
+    size: usize,
+    genetics: GeneticParams,
+    // Basic bots with these priorities, plus this many with priorities drawn each generation, plus saved models
+    priorities: Vec<[usize; 14]>,
+    random_basic: usize,
+    models: Vec<String>,
+    // A hybrid bot solving the endgame above this total, on top of the basic bot, 0 leaves it out
+    // It is also benchmarked against the champion at the end of the run
+    hybrid_threshold: i64,
+    games: usize,
+    // Seats per table, above 2 run_table_tournament seats opponents and agents together at every table
+    // and every agent plays about games matches instead of games against each opponent
+    table_size: usize,
+    // Race the agents instead of a fixed schedule, games becomes the most any agent plays against each opponent
+    racing: bool,
+    racing_batch: usize,
+    confidence_z: f64,
+    tokens: u32,
+    logged_matches: usize,
+    benchmark_simulations: usize,
+    benchmark_games: usize,
+    // Generations between hall of fame inductions and games against each member when rating a newcomer
+    hall_every: usize,
+    hall_games: usize,
+    // Breeding: "nod" is Agency::genetic_generation, "seeded" is seeded_generation, only seeded reproduces a run exactly
+    engine: String,
+    // Probe positions for behaviour diversity, and fitness sharing between species of agents that play alike
+    // Crossovers within a species need the seeded engine, Nod's breeding only sees the shared fitness
+    probes: usize,
+    speciation: bool,
+    species_threshold: f64,
+    model_out: String,
+    game_log: String,
+    checkpoint: String,
+    config_out: String,
+    hall: String,
+    // Per generation metrics, JSON lines when the name ends in .jsonl, otherwise CSV
+    metrics: String,
+    // Self-play training with the reinforce command: games in all, games between checks against the basic bot,
+    // the learning settings and the model it saves
+    reinforce_games: usize,
+    reinforce_eval_every: usize,
+    reinforce: ReinforceParams,
+    reinforce_model: String
+}
+
+impl Default for Experiment {
+    fn default() -> Experiment {
+        Experiment {
+            rules: String::from("standard"),
+            seed: None,
+            generations: 200,
+            checkpoint_every: 5,
+            report_every: 10,
+            source: String::from("decp_3"),
+            shape: vec![],
+            hidden: String::from("tanh"),
+            output: String::from("sigmoid"),
+            encoder: String::from("classic-v1"),
+            size: 150,
+            genetics: GeneticParams { survivors: 75, parents: 10, crossovers: 35, mutants: 30, mutation_passes: 5, mutation_rate: 0.005, mutation_size: 0.2 },
+            priorities: vec![BasicBot::REFERENCE],
+            random_basic: 0,
+            hybrid_threshold: 0,
+            models: vec![],
+            games: 150,
+            table_size: 2,
+            racing: false,
+            racing_batch: 25,
+            confidence_z: 1.96,
+            tokens: 1,
+            logged_matches: 10,
+            benchmark_simulations: 200,
+            benchmark_games: 150,
+            hall_every: 10,
+            hall_games: 100,
+            probes: 200,
+            engine: String::from("nod"),
+            speciation: false,
+            species_threshold: 0.3,
+            model_out: String::from("decp_7"),
+            game_log: String::from("decp_7_games.jsonl"),
+            checkpoint: String::from("decp_7"),
+            config_out: String::from("decp_7_config.toml"),
+            hall: String::from("decp_7_hall.txt"),
+            metrics: String::from("decp_7_metrics.csv"),
+            reinforce_games: 0,
+            reinforce_eval_every: 1000,
+            reinforce: ReinforceParams { learning_rate: 0.05, difference_step: 1e-4, temperature: 0.1, games_per_update: 50, table_size: 2 },
+            reinforce_model: String::from("reinforce")
+        }
+    }
+}
+
+impl Experiment {
+    fn set(&mut self, key: &str, value: &TomlValue) -> Result<(), String> {
+        match key {
+            "run.rules" => self.rules = toml_text(key, value)?,
+            "run.seed" => self.seed = Some(toml_number(key, value)?),
+            "run.generations" => self.generations = toml_number(key, value)?,
+            "run.checkpoint_every" => self.checkpoint_every = toml_number(key, value)?,
+            "run.report_every" => self.report_every = toml_number(key, value)?,
+            "population.source" => self.source = toml_text(key, value)?,
+            "population.shape" => self.shape = toml_list(key, value)?.iter().map(|x| toml_number(key, x)).collect::<Result<Vec<usize>, String>>()?,
+            "population.hidden" => self.hidden = toml_text(key, value)?,
+            "population.output" => self.output = toml_text(key, value)?,
+            "population.encoder" => {
+                let tag = toml_text(key, value)?;
+                if encoder_by_tag(&tag).is_none() {
+                    return Err(format!("unknown encoder {}", tag));
+                }
+                self.encoder = tag
+            },
+            "population.size" => self.size = toml_number(key, value)?,
+            "genetics.survivors" => self.genetics.survivors = toml_number(key, value)?,
+            "genetics.parents" => self.genetics.parents = toml_number(key, value)?,
+            "genetics.crossovers" => self.genetics.crossovers = toml_number(key, value)?,
+            "genetics.mutants" => self.genetics.mutants = toml_number(key, value)?,
+            "genetics.mutation_passes" => self.genetics.mutation_passes = toml_number(key, value)?,
+            "genetics.mutation_rate" => self.genetics.mutation_rate = toml_number(key, value)?,
+            "genetics.mutation_size" => self.genetics.mutation_size = toml_number(key, value)?,
+            "genetics.engine" => {
+                let engine = toml_text(key, value)?;
+                if engine != "nod" && engine != "seeded" {
+                    return Err(format!("unknown breeding engine {}, use nod or seeded", engine));
+                }
+                self.engine = engine
+            },
+            "opponents.priorities" => {
+                let mut all: Vec<[usize; 14]> = Vec::new();
+                for list in toml_list(key, value)? {
+                    let ranks: Vec<usize> = toml_list(key, &list)?.iter().map(|x| toml_number(key, x)).collect::<Result<Vec<usize>, String>>()?;
+                    all.push(ranks.try_into().map_err(|_| format!("{} needs 14 ranks in each list", key))?);
+                }
+                self.priorities = all;
+            },
+            "opponents.random_basic" => self.random_basic = toml_number(key, value)?,
+            "opponents.hybrid_threshold" => self.hybrid_threshold = toml_number(key, value)?,
+            // Configs from before the solver searched to the end set its depth, there is nothing left to set
+            "opponents.hybrid_plies" => (),
+            "opponents.models" => self.models = toml_list(key, value)?.iter().map(|x| toml_text(key, x)).collect::<Result<Vec<String>, String>>()?,
+            "evaluation.games" => self.games = toml_number(key, value)?,
+            "evaluation.table_size" => {
+                let size: usize = toml_number(key, value)?;
+                if size < 2 {
+                    return Err(format!("{} needs at least two seats", key));
+                }
+                self.table_size = size
+            },
+            "evaluation.racing" => self.racing = toml_flag(key, value)?,
+            "evaluation.racing_batch" => self.racing_batch = toml_number(key, value)?,
+            "evaluation.confidence_z" => self.confidence_z = toml_number(key, value)?,
+            "evaluation.tokens" => self.tokens = toml_number(key, value)?,
+            "evaluation.logged_matches" => self.logged_matches = toml_number(key, value)?,
+            "evaluation.benchmark_simulations" => self.benchmark_simulations = toml_number(key, value)?,
+            "evaluation.benchmark_games" => self.benchmark_games = toml_number(key, value)?,
+            "ratings.hall_every" => self.hall_every = toml_number(key, value)?,
+            "ratings.hall_games" => self.hall_games = toml_number(key, value)?,
+            "diversity.probes" => self.probes = toml_number(key, value)?,
+            "diversity.speciation" => self.speciation = toml_flag(key, value)?,
+            "diversity.species_threshold" => self.species_threshold = toml_number(key, value)?,
+            "output.model" => self.model_out = toml_text(key, value)?,
+            "output.game_log" => self.game_log = toml_text(key, value)?,
+            "output.checkpoint" => self.checkpoint = toml_text(key, value)?,
+            "output.config" => self.config_out = toml_text(key, value)?,
+            "output.hall" => self.hall = toml_text(key, value)?,
+            "output.metrics" => self.metrics = toml_text(key, value)?,
+            "reinforce.games" => self.reinforce_games = toml_number(key, value)?,
+            "reinforce.eval_every" => self.reinforce_eval_every = toml_number::<usize>(key, value)?.max(1),
+            "reinforce.learning_rate" => self.reinforce.learning_rate = toml_number(key, value)?,
+            "reinforce.difference_step" => self.reinforce.difference_step = toml_number(key, value)?,
+            "reinforce.temperature" => self.reinforce.temperature = toml_number(key, value)?,
+            "reinforce.games_per_update" => self.reinforce.games_per_update = toml_number::<usize>(key, value)?.max(1),
+            "reinforce.table_size" => {
+                let size: usize = toml_number(key, value)?;
+                if size < 2 {
+                    return Err(format!("{} needs at least two seats", key));
+                }
+                self.reinforce.table_size = size
+            },
+            "reinforce.model" => self.reinforce_model = toml_text(key, value)?,
+            _ => return Err(format!("unknown setting {}", key))
+        }
+        Ok(())
+    }
+
+    fn read(path: &str) -> Result<Experiment, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut experiment = Experiment::default();
+        for (key, value) in parse_toml(&text).map_err(|e| format!("{} {}", path, e))? {
+            experiment.set(&key, &value).map_err(|e| format!("{}: {}", path, e))?;
+        }
+        Ok(experiment)
+    }
+
+    // Settings from the command line: experiment files and section.key=value overrides apply in order,
+    // bare words set the positional keys in turn
+    fn from_args(args: &[String], positional: &[&str]) -> Result<Experiment, String> {
+        let mut experiment = Experiment::default();
+        let mut used: usize = 0;
+        for arg in args.iter() {
+            if arg.contains('=') {
+                experiment.apply_override(arg)?;
+            } else if arg.ends_with(".toml") {
+                experiment = Experiment::read(arg)?;
+            } else {
+                let key = positional.get(used).ok_or(format!("unexpected argument {}", arg))?;
+                experiment.set(key, &TomlValue::Word(arg.clone()))?;
+                used += 1;
             }
         }
-        let choice = self.hand.remove(selection - 1);
-        return choice.clone();
+        Ok(experiment)
     }
 
-    // Take turn
-    fn take_turn(&mut self, current_number: i64) -> Card {
-        println!("The current number is: {}\nYou have cards ({},{},{})\nType 1,2,3 to pick a card)", current_number, self.hand[0], self.hand[1], self.hand[2]);
-        let choice: Card = self.play_card();
-        return choice;
+    // Apply a section.key=value override from the command line
+    fn apply_override(&mut self, arg: &str) -> Result<(), String> {
+        let (key, value) = arg.split_once('=').ok_or(format!("{} is not a section.key=value override", arg))?;
+        let chars: Vec<char> = value.chars().collect();
+        let mut pos: usize = 0;
+        let value = match parse_toml_value(&chars, &mut pos) {
+            Some(v) if pos == chars.len() => v,
+            _ => TomlValue::Str(value.to_string())
+        };
+        self.set(key.trim(), &value)
     }
 
-}
+    fn to_toml(&self) -> String {
+        let g = &self.genetics;
+        let list = |items: Vec<String>| format!("[{}]", items.join(", "));
+        let priorities: Vec<String> = self.priorities.iter().map(|p| list(p.iter().map(|x| x.to_string()).collect())).collect();
+        let mut text = String::new();
+        text.push_str(&format!("[run]\nrules = \"{}\"\n", self.rules));
+        if let Some(seed) = self.seed {
+            text.push_str(&format!("seed = {}\n", seed));
+        }
+        text.push_str(&format!("generations = {}\ncheckpoint_every = {}\nreport_every = {}\n\n", self.generations, self.checkpoint_every, self.report_every));
+        text.push_str(&format!("[population]\nsource = \"{}\"\nshape = {}\nhidden = \"{}\"\noutput = \"{}\"\nencoder = \"{}\"\nsize = {}\n\n",
+            self.source, list(self.shape.iter().map(|x| x.to_string()).collect()), self.hidden, self.output, self.encoder, self.size));
+        text.push_str(&format!("[genetics]\nsurvivors = {}\nparents = {}\ncrossovers = {}\nmutants = {}\nmutation_passes = {}\nmutation_rate = {:?}\nmutation_size = {:?}\nengine = \"{}\"\n\n",
+            g.survivors, g.parents, g.crossovers, g.mutants, g.mutation_passes, g.mutation_rate, g.mutation_size, self.engine));
+        text.push_str(&format!("[opponents]\npriorities = {}\nrandom_basic = {}\nmodels = {}\nhybrid_threshold = {}\n\n",
+            list(priorities), self.random_basic, list(self.models.iter().map(|m| format!("\"{}\"", m)).collect()), self.hybrid_threshold));
+        text.push_str(&format!("[evaluation]\ngames = {}\ntable_size = {}\nracing = {}\nracing_batch = {}\nconfidence_z = {:?}\ntokens = {}\nlogged_matches = {}\nbenchmark_simulations = {}\nbenchmark_games = {}\n\n",
+            self.games, self.table_size, self.racing, self.racing_batch, self.confidence_z, self.tokens, self.logged_matches, self.benchmark_simulations, self.benchmark_games));
+        text.push_str(&format!("[ratings]\nhall_every = {}\nhall_games = {}\n\n", self.hall_every, self.hall_games));
+        text.push_str(&format!("[diversity]\nprobes = {}\nspeciation = {}\nspecies_threshold = {:?}\n\n", self.probes, self.speciation, self.species_threshold));
+        text.push_str(&format!("[output]\nmodel = \"{}\"\ngame_log = \"{}\"\ncheckpoint = \"{}\"\nconfig = \"{}\"\nhall = \"{}\"\nmetrics = \"{}\"\n",
+            self.model_out, self.game_log, self.checkpoint, self.config_out, self.hall, self.metrics));
+        let r = &self.reinforce;
+        text.push_str(&format!("\n[reinforce]\ngames = {}\neval_every = {}\nlearning_rate = {:?}\ndifference_step = {:?}\ntemperature = {:?}\ngames_per_update = {}\ntable_size = {}\nmodel = \"{}\"\n",
+            self.reinforce_games, self.reinforce_eval_every, r.learning_rate, r.difference_step, r.temperature, r.games_per_update, r.table_size, self.reinforce_model));
+        text
+    }
 
-//// Basic Bot ////
-#[derive(Default)]
-struct BasicBot {
-    hand: Vec<Card>,
-    losses: u32,
-    priorities: [usize; 14]
-}
+    // Checked when the tag is set
+    fn encoder(&self) -> &'static dyn Encoder {
+        encoder_by_tag(&self.encoder).unwrap()
+    }
 
-impl BasicBot {
-    fn randomize(&mut self) -> i32 {
-        for i in 0..14 {
-            self.priorities[i] = i;
+    // Header for a model trained by this run, with the trainer and the run's settings ahead of the given metadata
+    fn model_header(&self, trainer: &str, metadata: Vec<(&str, String)>) -> ModelHeader {
+        let mut pairs: Vec<(String, String)> = vec![(String::from("trainer"), trainer.to_string()), (String::from("rules"), self.rules.clone()),
+            (String::from("source"), self.source.clone())];
+        if let Some(seed) = self.seed {
+            pairs.push((String::from("seed"), seed.to_string()));
         }
-        let mut rng = thread_rng();
-        self.priorities.shuffle(&mut rng);
-        return 0;
+        pairs.extend(metadata.into_iter().map(|(k, v)| (k.to_string(), v)));
+        ModelHeader { encoder: self.encoder(), hidden: self.hidden.clone(), output: self.output.clone(), metadata: pairs }
     }
 
-    fn play_card(&mut self, current_number: i64) -> Card {
-        for card in self.priorities {
-            for hcard in 0..3 {
-                if self.hand[hcard].num_form() == card as i32 && self.hand[hcard].new_number(current_number) <= 99{
-                    return self.hand.remove(hcard);
-                }
-            }
+    // The starting network every agent is grown from, it has to read the inputs of the run's encoder
+    // and, when the ruleset has cards played either way, end in the declaration heads
+    fn root(&self) -> Result<Network, String> {
+        let rules = Ruleset::by_name(&self.rules).ok_or(format!("unknown ruleset {}", self.rules))?;
+        if self.shape.is_empty() {
+            let mut brain = load_model_for(&self.source, &self.hidden, &self.output, self.encoder())?;
+            check_heads(brain.process(vec![0.0; self.encoder().inputs()]).len(), &rules)?;
+            Ok(brain)
+        } else if self.shape.first() != Some(&self.encoder().inputs()) {
+            Err(format!("shape {:?} does not start with the {} inputs of encoder {}", self.shape, self.encoder().inputs(), self.encoder))
+        } else {
+            check_heads(self.shape.last().copied().unwrap_or(0), &rules)?;
+            Ok(Nod::build_typical_model(self.shape.clone(), self.hidden.clone(), self.output.clone()))
+        }
+    }
+
+    // The opponent team for one generation, saved models play with the encoder they were trained with
+    fn opponents(&self, rng: &mut StdRng) -> Result<Vec<Box<dyn Player>>, String> {
+        let mut team: Vec<Box<dyn Player>> = Vec::new();
+        for priorities in self.priorities.iter() {
+            team.push(Box::new(BasicBot::new(*priorities)));
+        }
+        for _i in 0..self.random_basic {
+            let mut basic: Box<dyn Player> = Box::new(BasicBot::default());
+            basic.rand(rng);
+            team.push(basic);
+        }
+        for name in self.models.iter() {
+            let (brain, header) = load_model(name, &self.hidden, &self.output)?;
+            team.push(Box::new(BotNod { hand: vec![], losses: 0, brain, encoder: header.encoder }));
         }
-        return self.hand.remove(0);
+        if let Some(hybrid) = self.hybrid() {
+            team.push(hybrid);
+        }
+        Ok(team)
     }
 
-    fn take_turn(&mut self, current_number: i64) -> Card {
-        let choice = self.play_card(current_number);
-        return choice;
+    fn hybrid(&self) -> Option<Box<dyn Player>> {
+        if self.hybrid_threshold <= 0 {
+            return None;
+        }
+        let fallback: Box<dyn Player> = Box::new(BasicBot::reference());
+        Some(Box::new(HybridBot::new(fallback, self.hybrid_threshold)))
     }
 }
 
 
-//// Nod Bot ////
-struct BotNod {
-    hand: Vec<Card>,
-    losses: u32,
-    brain: Network
+/////////////////
+/// Training ///
+///////////////
+// Shape of one genetic generation, the population keeps its size
+// These are the seven arguments of Agency::genetic_generation, in its order
+#[derive(Clone)]
+struct GeneticParams {
+    survivors: usize,
+    parents: usize,
+    crossovers: usize,
+    mutants: usize,
+    mutation_passes: usize,
+    mutation_rate: f64,
+    mutation_size: f64
 }
 
-impl BotNod {
-    // Select card
-    fn play_card(&mut self, current_number: i64, discard: HashMap<Card, usize>, direction: i64, num_players: usize) -> Card {
-        let mut inputs: Vec<f64> = vec![direction as f64, 1.0 / (num_players as f64)];
-        let mut used: Vec<f64> = vec![Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
-        Card::Jack, Card::Queen, Card::King, Card::OneEye].iter().map(|x| (*discard.get(x).unwrap() as f64) / 4.0).collect();
-        let mut hand: Vec<f64> = vec![Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
-        Card::Jack, Card::Queen, Card::King, Card::OneEye].iter().map(|x| (self.hand.iter().filter(|&y| y == x).count() as f64 + 1.0).ln()).collect();
-        inputs.append(&mut used);
-        inputs.append(&mut hand);
-        inputs.push(current_number as f64 / 99.0);
-        if current_number > 89 {
-            if current_number > 98 {
-                inputs.push(1.0)
-            } else {
-                inputs.push(0.0)
+// Mutate each weight with probability mutation_rate by up to mutation_size, mutation_passes times
+fn mutate_weights(weights: &mut [f64], params: &GeneticParams, rng: &mut StdRng) {
+    for _ in 0..params.mutation_passes {
+        for w in weights.iter_mut() {
+            if rng.gen_bool(params.mutation_rate) {
+                *w += rng.gen_range(-params.mutation_size..=params.mutation_size);
             }
-            inputs.push(1.0);
-            inputs.push((current_number as f64 - 90.0) / 9.0);
-        } else {
-            inputs.push(0.0);
-            inputs.push(0.0);
-            inputs.push(0.0)
         }
-        inputs.push(match current_number {
-            x if x < 80 => -1.0,
-            x if x > 79 && x < 90 => 0.0,
-            x if x > 89 => 1.0,
-            _ => 0.0
-        });
-        
-        let mut outputs: Vec<f64> = self.brain.process(inputs);
-        let mut big: usize = 0;
-        for i in 0..outputs.len() {
-            for j in 0..outputs.len() {
-                if outputs[j] > outputs[big] {
-                    big = j;
-                }
-            }
-            for j in 0..3 {
-                if self.hand[j].num_form() == big as i32 {
-                    return self.hand.remove(j);
+    }
+}
+
+// Seeded alternative to Agency::genetic_generation, which draws from Nod's own thread_rng, chosen with genetics.engine
+// It is our reading of the seven arguments and not a copy of Nod's breeding, so runs with it are not comparable
+// with runs on Nod's. Expects the agency sorted best first by reorder. The top survivors are kept unchanged,
+// then come crossovers of two of the best parents, mutated copies of parents, and mutated copies of survivors for the rest
+// With species, given in agency order, crossovers only pair parents of the same species
+fn seeded_generation(agency: &mut Agency, params: &GeneticParams, species: Option<&Vec<usize>>, rng: &mut StdRng) {
+    let population: usize = agency.agents.len();
+    let survivors: usize = params.survivors.clamp(1, population);
+    let parents: usize = params.parents.clamp(1, survivors);
+    let weights: Vec<Vec<f64>> = agency.agents.iter().map(|a| a.network.get_weights()).collect();
+
+    for i in survivors..population {
+        let mut child: Vec<f64>;
+        if i < survivors + params.crossovers {
+            let first = rng.gen_range(0..parents);
+            child = weights[first].clone();
+            let mates: Vec<usize> = match species {
+                Some(species) => (0..parents).filter(|&j| species[j] == species[first]).collect(),
+                None => vec![]
+            };
+            let other = if mates.is_empty() { &weights[rng.gen_range(0..parents)] } else { &weights[mates[rng.gen_range(0..mates.len())]] };
+            for (w, o) in child.iter_mut().zip(other.iter()) {
+                if rng.gen_bool(0.5) {
+                    *w = *o;
                 }
             }
-            outputs[big] = 0.0;
+        } else if i < survivors + params.crossovers + params.mutants {
+            child = weights[rng.gen_range(0..parents)].clone();
+        } else {
+            child = weights[rng.gen_range(0..survivors)].clone();
         }
-        return self.hand.remove(0);
+        mutate_weights(&mut child, params, rng);
+        agency.agents[i].network.set_weights(child);
     }
+}
 
-    // Take turn
-    fn take_turn(&mut self, current_number: i64, discard: HashMap<Card, usize>, direction: i64, num_players: usize) -> Card {
-        self.play_card(current_number, discard, direction, num_players)
+// Wilson score interval for a loss rate, z standard deviations wide, it stays inside 0 to 1 even on few games
+fn wilson_interval(losses: usize, games: usize, z: f64) -> (f64, f64) {
+    if games == 0 {
+        return (0.0, 1.0);
     }
+    let n = games as f64;
+    let p = (losses as f64 / n).min(1.0);
+    let z2 = z * z;
+    let centre = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half = z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+    ((centre - half).max(0.0), (centre + half).min(1.0))
 }
 
+// An agent's loss rate so far with its confidence interval
+#[derive(Clone, Copy)]
+struct LossEstimate {
+    losses: usize,
+    games: usize,
+    low: f64,
+    high: f64
+}
 
-///////////////////
-/// Player Set ///
-/////////////////
+impl LossEstimate {
+    fn new(losses: usize, games: usize, z: f64) -> LossEstimate {
+        let (low, high) = wilson_interval(losses, games, z);
+        LossEstimate { losses, games, low, high }
+    }
 
-enum Player {
-    Human(Person),
-    Bot(BasicBot),
-    Nod(BotNod)
+    fn rate(&self) -> f64 {
+        if self.games == 0 { 0.0 } else { self.losses as f64 / self.games as f64 }
+    }
 }
 
-impl Turn for Player {
-    fn take_turn(&mut self, current_number: i64, discard: HashMap<Card, usize>, direction: i64, num_players: usize) -> Card {
-        let card: Card = match self {
-            Player::Human(f) => f.take_turn(current_number),
-            Player::Bot(f) => f.take_turn(current_number),
-            Player::Nod(f ) => f.take_turn(current_number, discard, direction, num_players)
-        };
-        return card;
-    }
+// How a team is raced: the size of the elite, games per round, the most games against each opponent and the
+// width of the confidence bounds in standard deviations
+struct RaceParams {
+    elite: usize,
+    batch: usize,
+    max_games: usize,
+    z: f64
 }
 
-impl Player {
-    fn take_card(&mut self, card: Card) {
-        match self {
-            Player::Human(f) => f.hand.push(card),
-            Player::Bot(f) =>f.hand.push(card),
-            Player::Nod(f) => f.hand.push(card)
+// Racing evaluation: every agent still in the race plays batch more games against each opponent per round
+// An agent leaves the race once elite other agents are surely better than it, once it is surely among the elite,
+// or after max_games against each opponent, so only the agents near the elite cutoff play the full schedule
+// Returns the opponents' losses and an estimate for every agent
+fn race_team(opponents: Vec<Box<dyn Player>>, team: Vec<Box<dyn Player>>, race: &RaceParams, tokens: u32, rules: &Ruleset, rng: &mut StdRng) -> (Vec<usize>, Vec<LossEstimate>) {
+    let n = team.len();
+    let per_round = race.batch.max(1);
+    let elite = race.elite.clamp(1, n.max(1));
+    let (max_games, z) = (race.max_games, race.z);
+    let mut opponent_losses: Vec<usize> = vec![0; opponents.len()];
+    let mut estimates: Vec<LossEstimate> = vec![LossEstimate::new(0, 0, z); n];
+    let mut active: Vec<usize> = (0..n).collect();
+    let mut played: usize = 0;
+    while !active.is_empty() && played < max_games {
+        let round = per_round.min(max_games - played);
+        let racers: Vec<Box<dyn Player>> = active.iter().map(|&j| team[j].clone()).collect();
+        let results = run_team_round_robin_1v1(opponents.clone(), racers, round, tokens, rules, rng);
+        for (o, losses) in results[0].iter().enumerate() {
+            opponent_losses[o] += losses;
+        }
+        for (k, &j) in active.iter().enumerate() {
+            let e = estimates[j];
+            estimates[j] = LossEstimate::new(e.losses + results[1][k], e.games + round * opponents.len(), z);
         }
+        played += round;
+        // The elite-th best upper bound: any agent whose lower bound is above it has elite agents surely better
+        let mut highs: Vec<f64> = estimates.iter().map(|e| e.high).collect();
+        highs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mut lows: Vec<f64> = estimates.iter().map(|e| e.low).collect();
+        lows.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let loser_cutoff = highs[elite - 1];
+        let winner_cutoff = if elite < n { lows[elite] } else { f64::MAX };
+        active.retain(|&j| estimates[j].low <= loser_cutoff && estimates[j].high >= winner_cutoff);
     }
+    (opponent_losses, estimates)
+}
 
-    fn rand(&mut self) {
-        match self {
-            Player::Human(_) => 0,
-            Player::Bot(f) => f.randomize(),
-            Player::Nod(_) => 0
-        };
+// Mean loss rate of the count best agents by selection score
+fn top_loss_rate(estimates: &[LossEstimate], scores: &[f64], count: usize) -> f64 {
+    let mut order: Vec<usize> = (0..estimates.len()).collect();
+    order.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap());
+    let top: Vec<f64> = order.iter().take(count).map(|&j| estimates[j].rate()).collect();
+    if top.is_empty() { 0.0 } else { top.iter().sum::<f64>() / top.len() as f64 }
+}
 
-    }
+// Everything needed to carry on a genetic run: the population with its scores, where the run was, and how it was set up
+// The rng is not stored, each generation seeds its own from the run seed and the generation number instead
+struct Checkpoint {
+    generation: usize,
+    seed: u64,
+    games_played: usize,
+    rules: String,
+    genetics: GeneticParams,
+    tokens: u32,
+    scores: Vec<f64>,
+    weights: Vec<Vec<f64>>
+}
 
-    fn lose(&mut self) {
-        match self {
-            Player::Human(f) => f.losses += 1,
-            Player::Bot(f) => f.losses += 1,
-            Player::Nod(f) => f.losses += 1
-        };
+impl Checkpoint {
+    // The population is saved as name_root, the shared model, plus every agent's weights in name_checkpoint.txt
+    fn write(&self, name: &str, agency: &Agency, header: &ModelHeader) -> io::Result<()> {
+        if let Some(root) = agency.networks_iter().next() {
+            save_model(&root, &format!("{}_root", name), header)?;
+        }
+        let g = &self.genetics;
+        let mut text = format!("generation {}\nseed {}\ngames_played {}\nrules {}\ngenetics {} {} {} {} {} {} {}\ntokens {}\n",
+            self.generation, self.seed, self.games_played, self.rules, g.survivors, g.parents, g.crossovers, g.mutants,
+            g.mutation_passes, g.mutation_rate, g.mutation_size, self.tokens);
+        for (score, weights) in self.scores.iter().zip(self.weights.iter()) {
+            let weights: Vec<String> = weights.iter().map(|w| w.to_string()).collect();
+            text.push_str(&format!("agent {} {}\n", score, weights.join(" ")));
+        }
+        // Write beside the old checkpoint and swap, so a crash mid-write leaves the last one whole
+        let path = format!("{}_checkpoint.txt", name);
+        let partial = format!("{}.partial", path);
+        std::fs::write(&partial, text)?;
+        std::fs::rename(&partial, &path)
     }
 
-    fn clear_hand(&mut self) {
-        match self {
-            Player::Human(f) => f.hand.clear(),
-            Player::Bot(f) => f.hand.clear(),
-            Player::Nod(f) => f.hand.clear()
+    fn read(name: &str) -> io::Result<Checkpoint> {
+        let path = format!("{}_checkpoint.txt", name);
+        let text = std::fs::read_to_string(&path)?;
+        let bad = |line: usize| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{} is not a checkpoint line", path, line + 1));
+        let mut checkpoint = Checkpoint { generation: 0, seed: 0, games_played: 0, rules: String::from("standard"),
+            genetics: GeneticParams { survivors: 0, parents: 0, crossovers: 0, mutants: 0, mutation_passes: 0, mutation_rate: 0.0, mutation_size: 0.0 },
+            tokens: 1, scores: vec![], weights: vec![] };
+        for (number, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let value = |i: usize| words.get(i).and_then(|w| w.parse::<f64>().ok()).ok_or(bad(number));
+            match words.first() {
+                Some(&"generation") => checkpoint.generation = value(1)? as usize,
+                Some(&"seed") => checkpoint.seed = words.get(1).and_then(|w| w.parse::<u64>().ok()).ok_or(bad(number))?,
+                Some(&"games_played") => checkpoint.games_played = value(1)? as usize,
+                Some(&"rules") => checkpoint.rules = words.get(1).ok_or(bad(number))?.to_string(),
+                Some(&"genetics") => checkpoint.genetics = GeneticParams { survivors: value(1)? as usize, parents: value(2)? as usize,
+                    crossovers: value(3)? as usize, mutants: value(4)? as usize, mutation_passes: value(5)? as usize, mutation_rate: value(6)?,
+                    mutation_size: value(7)? },
+                Some(&"tokens") => checkpoint.tokens = value(1)? as u32,
+                Some(&"agent") => {
+                    checkpoint.scores.push(value(1)?);
+                    checkpoint.weights.push((2..words.len()).map(value).collect::<io::Result<Vec<f64>>>()?);
+                },
+                None => (),
+                _ => return Err(bad(number))
+            }
         }
+        Ok(checkpoint)
     }
 
-    fn loss_count(&self) -> u32 {
-        match self {
-            Player::Human(f) => f.losses,
-            Player::Bot(f) => f.losses,
-            Player::Nod(f) => f.losses
+    fn capture(agency: &Agency, generation: usize, seed: u64, games_played: usize, rules: &Ruleset, genetics: &GeneticParams, tokens: u32) -> Checkpoint {
+        Checkpoint { generation, seed, games_played, rules: rules.name.to_string(), genetics: genetics.clone(), tokens,
+            scores: agency.agents.iter().map(|a| a.score).collect(), weights: agency.agents.iter().map(|a| a.network.get_weights()).collect() }
+    }
+
+    // Rebuild the population saved under name, in the same order and with the same scores
+    fn restore(&self, name: &str, encoder: &dyn Encoder) -> Result<Agency, String> {
+        let root = load_model_for(&format!("{}_root", name), "tanh", "sigmoid", encoder)?;
+        let mut agency = Nod::build_agency_from_root(root, self.weights.len(), false);
+        for (agent, (score, weights)) in agency.agents.iter_mut().zip(self.scores.iter().zip(self.weights.iter())) {
+            agent.network.set_weights(weights.clone());
+            agent.score = *score;
         }
+        Ok(agency)
     }
 }
 
-////////////
-/// Deck //
-//////////
-fn build_deck() -> Vec<Card>{
+// Self-play policy gradient on the BotNod features, an alternative to the genetic loop that learns from every card played
+// Nod has no backpropagation, so the gradient is taken by central differences over get_weights and set_weights
+#[derive(Clone)]
+struct ReinforceParams {
+    learning_rate: f64,
+    // Nudge given to each weight when taking differences
+    difference_step: f64,
+    // Softmax temperature on the card scores, lower plays closer to BotNod's argmax
+    temperature: f64,
+    games_per_update: usize,
+    table_size: usize
+}
 
-    let mut deck: Vec<Card> = Vec::new();
-    for _i in 0..2 {
-        for c in [Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
-        Card::Queen, Card::King, Card::OneEye] {
-        deck.push(c)
-        };
-    };
-    for _i in 0..2 {
-        for c in [Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
-        Card::Jack, Card::Queen, Card::King] {
-            deck.push(c)
-        };
-    };
-    return deck;
+// One card choice: what the network saw, the chance it gave each rank in hand and the rank it played
+#[derive(Clone)]
+struct Decision {
+    inputs: Vec<f64>,
+    outputs: usize,
+    probabilities: Vec<(usize, f64)>,
+    chosen: usize
 }
 
+// BotNod that samples its card from a softmax over the scores of the ranks it holds, and remembers each choice
+#[derive(Clone)]
+pub struct LearnerBot {
+    hand: Vec<Card>,
+    losses: u32,
+    brain: Network,
+    encoder: &'static dyn Encoder,
+    temperature: f64,
+    rng: StdRng,
+    decisions: Vec<Decision>
+}
 
-/////////////
-/// GAME ///
-///////////
-
-// Main game run sequence, will return the losing player
-fn run_game(players: &mut Vec<&mut Player>, first_player: i64) -> i64{
-    let num_players: usize = players.len();
-    if players.len() == 0{
-        return -1;
-    }
-    let mut deck: Vec<Card> = build_deck();
-    let mut discard: HashMap<Card, usize> = HashMap::new();
-    for c in [Card::Ace, Card::Two, Card::Three, Card::Four, Card::Five, Card::Six, Card::Seven, Card::Eight, Card::Nine, Card::Ten,
-    Card::Jack, Card::Queen, Card::King, Card::OneEye] {
-        discard.insert(c, 0);
-    }
-    let mut current_player = first_player;
-    let mut current_number: i64 = 0;
-    let mut direction: i64 = 1;
-    let mut rng = rand::thread_rng();
-    let mut end: bool = false;
+impl LearnerBot {
+    fn play_card(&mut self, state: &GameState) -> Play {
+        let inputs: Vec<f64> = self.encoder.encode(&self.hand, state);
+        let mut outputs: Vec<f64> = self.brain.process(inputs.clone());
+        let size: usize = outputs.len();
+        let heads: Vec<f64> = if outputs.len() > 14 { outputs.split_off(14) } else { vec![] };
+        let mut ranks: Vec<usize> = self.hand.iter().map(|c| c.num_form() as usize).collect();
+        ranks.sort();
+        ranks.dedup();
+        let top: f64 = ranks.iter().map(|&r| outputs[r]).fold(f64::MIN, f64::max);
+        let weights: Vec<f64> = ranks.iter().map(|&r| ((outputs[r] - top) / self.temperature).exp()).collect();
+        let sum: f64 = weights.iter().sum();
+        let probabilities: Vec<(usize, f64)> = ranks.iter().zip(weights.iter()).map(|(&r, w)| (r, w / sum)).collect();
+        let mut pick: f64 = self.rng.gen::<f64>();
+        let mut chosen: usize = probabilities[probabilities.len() - 1].0;
+        for &(rank, chance) in probabilities.iter() {
+            if pick < chance {
+                chosen = rank;
+                break;
+            }
+            pick -= chance;
+        }
+        let index = self.hand.iter().position(|c| c.num_form() as usize == chosen).unwrap();
+        let card = self.hand.remove(index);
+        let declared = BotNod::declare(&card, &heads, state.total, state.rules);
+        self.decisions.push(Decision { inputs, outputs: size, probabilities, chosen });
+        Play { card, declared }
+    }
+}
 
-    deck.shuffle(&mut rng);
+impl Player for LearnerBot {
+    fn hand(&self) -> &Vec<Card> {
+        &self.hand
+    }
 
-    for i in 0..num_players {
-        players[i].clear_hand();
+    fn hand_mut(&mut self) -> &mut Vec<Card> {
+        &mut self.hand
     }
 
-    for _i in 0..3 {
-        for j in 0..num_players {
-            players[j].take_card(deck.remove(0))
-        }
+    fn loss_count(&self) -> u32 {
+        self.losses
     }
 
-    while !end {
-        let played = players[current_player as usize].take_turn(current_number, discard.clone(), direction, num_players);
-        let play_copy = played.clone();
-        discard.insert(played, discard.get(&play_copy).unwrap_or(&0) + 1);
-        if let Card::Eight = play_copy {
-        direction = direction * -1;
-        }
-        current_number = play_copy.new_number(current_number);
-        if current_number > 99 {
-            end = true;
-        } else {
-        current_player = current_player + direction;
-        if current_player >= num_players as i64{
-            current_player = 0;
-        } else if current_player <= -1 {
-            current_player = num_players as i64 - 1;
-        }
-        players[current_player as usize].take_card(deck.remove(0));
-        if deck.len() == 0 {
-            for key in discard.keys() {
-                for _ in 0..*discard.get(key).unwrap() {
-                    deck.push(key.clone())
-                }
+    fn add_losses(&mut self, count: u32) {
+        self.losses += count;
+    }
+
+    fn rand(&mut self, rng: &mut StdRng) {
+        self.rng = StdRng::seed_from_u64(rng.gen());
+    }
+
+    fn take_turn(&mut self, state: &GameState) -> Play {
+        self.play_card(state)
+    }
+}
+
+// The REINFORCE surrogate: each decision's outputs summed with fixed coefficients, its gradient is the policy gradient
+fn surrogate(brain: &mut Network, terms: &[(Vec<f64>, Vec<f64>)]) -> f64 {
+    terms.iter().map(|(inputs, coefficients)| {
+        brain.process(inputs.clone()).iter().zip(coefficients.iter()).map(|(o, c)| o * c).sum::<f64>()
+    }).sum()
+}
+
+// One REINFORCE step: raise the log chance of each choice in proportion to the reward of the game it came from
+// Only the 14 card scores are trained, the declaration heads are left as they are
+// Every weight costs two passes over the decisions, so keep games_per_update modest on large networks
+fn reinforce_update(brain: &mut Network, episodes: &[(Vec<Decision>, f64)], params: &ReinforceParams) {
+    let mut terms: Vec<(Vec<f64>, Vec<f64>)> = Vec::new();
+    for (decisions, reward) in episodes.iter() {
+        for d in decisions.iter() {
+            let mut coefficients: Vec<f64> = vec![0.0; d.outputs];
+            for &(rank, chance) in d.probabilities.iter() {
+                let played: f64 = if rank == d.chosen { 1.0 } else { 0.0 };
+                coefficients[rank] = reward * (played - chance) / params.temperature;
             }
-            deck.shuffle(&mut rng)
-        }
+            terms.push((d.inputs.clone(), coefficients));
         }
     }
-    players[current_player as usize].lose();
-    return current_player;
-
+    if terms.is_empty() {
+        return;
+    }
+    let weights: Vec<f64> = brain.get_weights();
+    let h = params.difference_step;
+    let mut nudged = weights.clone();
+    let mut gradient: Vec<f64> = vec![0.0; weights.len()];
+    for j in 0..weights.len() {
+        nudged[j] = weights[j] + h;
+        brain.set_weights(nudged.clone());
+        let up = surrogate(brain, &terms);
+        nudged[j] = weights[j] - h;
+        brain.set_weights(nudged.clone());
+        let down = surrogate(brain, &terms);
+        nudged[j] = weights[j];
+        gradient[j] = (up - down) / (2.0 * h);
+    }
+    let updated: Vec<f64> = weights.iter().zip(gradient.iter()).map(|(w, g)| w + params.learning_rate * g / terms.len() as f64).collect();
+    brain.set_weights(updated);
 }
 
-// Create a function that will run two sets of players against each other, facing each player against each player on the other set N times
-fn run_team_round_robin_1v1(mut team1: Vec<Player>, mut team2: Vec<Player>, num_games: usize) -> Vec<Vec<usize>> {
-    for i in 0..team1.len() {
-        for j in 0..team2.len() {
-            for k in 0..num_games {
-                let mut players = vec![&mut team1[i], &mut team2[j]];
-                run_game(&mut players, k as i64 % 2);
+// Train by self-play for the given number of games, every seat at the table is a learner on the same network
+// The bust scores -1 and every other seat 1/(table_size - 1), so rewards sum to zero at each table
+// Every eval_every games the network plays the basic bot and the loss rate is printed against games played,
+// the genetic loop prints the same so the two can be compared on sample efficiency
+fn train_reinforce(brain: &mut Network, encoder: &'static dyn Encoder, games: usize, eval_every: usize, params: &ReinforceParams, rules: &Ruleset, rng: &mut StdRng) {
+    let table_size: usize = params.table_size.max(2);
+    let mut played: usize = 0;
+    while played < games {
+        let mut learners: Vec<LearnerBot> = (0..table_size).map(|_| LearnerBot { hand: vec![], losses: 0, brain: brain.clone(), encoder, temperature: params.temperature,
+            rng: StdRng::seed_from_u64(rng.gen()), decisions: vec![] }).collect();
+        let mut episodes: Vec<(Vec<Decision>, f64)> = Vec::new();
+        for game in 0..params.games_per_update {
+            let loser = {
+                let mut players: Vec<&mut dyn Player> = learners.iter_mut().map(|l| l as &mut dyn Player).collect();
+                run_game(&mut players, (game % table_size) as i64, rules, rng, None) as usize
+            };
+            for (seat, learner) in learners.iter_mut().enumerate() {
+                let reward: f64 = if seat == loser { -1.0 } else { 1.0 / (table_size - 1) as f64 };
+                episodes.push((std::mem::take(&mut learner.decisions), reward));
+            }
+            played += 1;
+            if played.is_multiple_of(eval_every) {
+                let champion: Box<dyn Player> = Box::new(BotNod { hand: vec![], losses: 0, brain: brain.clone(), encoder });
+                let basic: Box<dyn Player> = Box::new(BasicBot::reference());
+                let results = run_team_round_robin_1v1(vec![basic], vec![champion], 150, 1, rules, rng);
+                println!("Games played: {}, loss rate against the basic bot: {}", played, results[1][0] as f64 / 150.0);
             }
         }
+        reinforce_update(brain, &episodes, params);
     }
-    let team1_losses: Vec<usize> = team1.iter().map(|f| f.loss_count() as usize).collect::<Vec<usize>>();
-    let team2_losses: Vec<usize> = team2.iter().map(|f| f.loss_count() as usize).collect::<Vec<usize>>();
-    
-    vec![team1_losses, team2_losses]
 }
 
 
-fn main() {
+
+fn main() -> Result<(), String> {
     let start = time::Instant::now();
-    //let root = Nod::build_typical_model(vec![30,2,14], String::from("relu"), String::from("sigmoid"));
-    let decp_root = Nod::model_from_txt(String::from("decp_3_types"), String::from("decp_3_weights"), String::from("decp_3_connections"), String::from("tanh"), String::from("sigmoid"));
+    // nod-99 replay <log> steps through a saved game log instead of training
+    if std::env::args().nth(1).as_deref() == Some("replay") {
+        let path = std::env::args().nth(2).expect("replay needs a log file");
+        replay(&path).expect("Failed to replay log");
+        return Ok(());
+    }
+    // nod-99 hall [file] prints the rating of every champion in a hall of fame
+    if std::env::args().nth(1).as_deref() == Some("hall") {
+        let path = std::env::args().nth(2).unwrap_or(String::from("decp_7_hall.txt"));
+        HallOfFame::read(&path).expect("Failed to read hall of fame").report();
+        return Ok(());
+    }
+    // nod-99 summary <metrics>... compares the metrics files of one or more runs
+    if std::env::args().nth(1).as_deref() == Some("summary") {
+        let paths: Vec<String> = std::env::args().skip(2).collect();
+        summary(&paths).expect("Failed to read metrics");
+        return Ok(());
+    }
+    // nod-99 model <name> checks a model file and prints its header, nod-99 bundle <name> [hidden output] packs an older three file model into one
+    if std::env::args().nth(1).as_deref() == Some("model") {
+        let name = std::env::args().nth(2).expect("model needs a model name");
+        describe_model(&name).expect("Failed to read model");
+        return Ok(());
+    }
+    if std::env::args().nth(1).as_deref() == Some("bundle") {
+        let name = std::env::args().nth(2).expect("bundle needs a model name");
+        let hidden = std::env::args().nth(3).unwrap_or(String::from("tanh"));
+        let output = std::env::args().nth(4).unwrap_or(String::from("sigmoid"));
+        let (brain, header) = load_model(&name, &hidden, &output).expect("Failed to load model");
+        save_model(&brain, &name, &header).expect("Failed to write model");
+        describe_model(&name).expect("Failed to read model");
+        return Ok(());
+    }
+    // nod-99 explain <model> <cards> <total> [key=value ...] shows why a model plays what it does, see explain_command
+    if std::env::args().nth(1).as_deref() == Some("explain") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        return explain_command(&args);
+    }
+    // nod-99 refbot speaks the bot protocol on stdin and stdout
+    if std::env::args().nth(1).as_deref() == Some("refbot") {
+        reference_bot().expect("Reference bot lost its connection");
+        return Ok(());
+    }
+    // nod-99 serve <port> <humans> <seats> <games> [model] hosts a table on localhost, nod-99 join <port> sits at it
+    if std::env::args().nth(1).as_deref() == Some("serve") {
+        let arg = |n: usize| std::env::args().nth(n).and_then(|x| x.parse::<usize>().ok()).expect("serve needs a port, humans, seats and games");
+        let mut rng = StdRng::seed_from_u64(rand::random::<u64>());
+        let listener = TcpListener::bind(("127.0.0.1", arg(2) as u16)).expect("Failed to bind the port");
+        let bots = table_bots(arg(4).saturating_sub(arg(3)), std::env::args().nth(6)).expect("Failed to load the model");
+        serve(listener, arg(3), bots, arg(5), time::Duration::from_secs(TURN_SECONDS), &Ruleset::standard(), &mut rng).expect("Server failed");
+        return Ok(());
+    }
+    if std::env::args().nth(1).as_deref() == Some("join") {
+        let port = std::env::args().nth(2).and_then(|x| x.parse::<u16>().ok()).expect("join needs a port");
+        join(port).expect("Lost the connection to the server");
+        return Ok(());
+    }
+    // nod-99 reinforce [games] [seed] [experiment.toml] [section.key=value ...] trains the run's starting model by
+    // self-play instead of the genetic loop, taking the source model, encoder, rules and the [reinforce] keys from
+    // the experiment like training does
+    if std::env::args().nth(1).as_deref() == Some("reinforce") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let mut experiment = Experiment::from_args(&args, &["reinforce.games", "run.seed"]).expect("Bad experiment setting");
+        let games = experiment.reinforce_games;
+        assert!(games > 0, "reinforce needs a number of games");
+        let rules = Ruleset::by_name(&experiment.rules).expect("Unknown ruleset");
+        assert!(experiment.reinforce.table_size <= rules.max_seats(), "reinforce.table_size is {} but the {} deck only keeps {} hands refilled",
+            experiment.reinforce.table_size, rules.name, rules.max_seats());
+        let seed: u64 = experiment.seed.unwrap_or_else(rand::random::<u64>);
+        experiment.seed = Some(seed);
+        println!("Training {} by self-play under the {} ruleset", experiment.source, rules.name);
+        println!("Seed: {}", seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut brain = experiment.root().expect("Failed to set up the starting model");
+        train_reinforce(&mut brain, experiment.encoder(), games, experiment.reinforce_eval_every, &experiment.reinforce, &rules, &mut rng);
+        let header = experiment.model_header("reinforce", vec![("games_played", games.to_string())]);
+        save_model(&brain, &experiment.reinforce_model, &header).expect("Failed to save the model");
+        println!("Time Since start: {:?}",time::Instant::now().duration_since(start));
+        return Ok(());
+    }
+    // nod-99 external <model> <command...> plays a saved model against a bot program speaking the protocol
+    if std::env::args().nth(1).as_deref() == Some("external") {
+        let model = std::env::args().nth(2).expect("external needs a model name");
+        let command: Vec<String> = std::env::args().skip(3).collect();
+        let (brain, header) = load_model(&model, "tanh", "sigmoid").expect("Failed to load model");
+        let mut rng = StdRng::seed_from_u64(rand::random::<u64>());
+        let mut champion = BotNod { hand: vec![], losses: 0, brain, encoder: header.encoder };
+        // One process for the whole session, the round robin would start one per copy
+        let mut external = ProcessBot::new(command, time::Duration::from_secs(2));
+        let mut lost: (usize, usize) = (0, 0);
+        for k in 0..150 {
+            let mut players: Vec<&mut dyn Player> = vec![&mut external, &mut champion];
+            match run_match(&mut players, 1, k % 2, &Ruleset::standard(), &mut rng, None).first() {
+                Some(0) => lost.1 += 1,
+                _ => lost.0 += 1
+            }
+        }
+        println!("Against {} the model lost {} of 150, the bot lost {} and made {} faults", model, lost.1, lost.0, external.faults());
+        return Ok(());
+    }
+    // Training runs are described by an experiment file, see Experiment for the keys and their defaults
+    // nod-99 [experiment.toml] [section.key=value ...] trains, overrides apply on top of the file in order
+    // The older nod-99 [rules] [seed] form still works
+    // nod-99 resume [config] carries on from the config recorded by a run, decp_7_config.toml by default
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let resuming: bool = args.first().map(|a| a.as_str()) == Some("resume");
+    let mut experiment = if resuming {
+        let path = args.get(1).cloned().unwrap_or(String::from("decp_7_config.toml"));
+        Experiment::read(&path).expect("Failed to read experiment config")
+    } else {
+        Experiment::from_args(&args, &["run.rules", "run.seed"]).expect("Bad experiment setting")
+    };
+    assert!(!experiment.racing || experiment.table_size == 2, "evaluation.racing races 1v1 only, it needs evaluation.table_size = 2");
+    let resume: Option<Checkpoint> = if resuming {
+        Some(Checkpoint::read(&experiment.checkpoint).expect("Failed to read checkpoint"))
+    } else {
+        None
+    };
+    let rules = match &resume {
+        Some(checkpoint) => Ruleset::by_name(&checkpoint.rules).expect("Unknown ruleset"),
+        None => Ruleset::by_name(&experiment.rules).expect("Unknown ruleset")
+    };
+    assert!(experiment.table_size <= rules.max_seats(), "evaluation.table_size is {} but the {} deck only keeps {} hands refilled",
+        experiment.table_size, rules.name, rules.max_seats());
+    println!("Training under the {} ruleset", rules.name);
+    // A missing seed is drawn here and recorded with the rest of the config
+    let seed: u64 = match (&resume, experiment.seed) {
+        (Some(checkpoint), _) => checkpoint.seed,
+        (None, Some(x)) => x,
+        (None, None) => rand::random::<u64>()
+    };
+    experiment.seed = Some(seed);
+    println!("Seed: {}", seed);
+    // The seed fixes every game, but Nod's breeding draws from its own generator
+    if experiment.engine != "seeded" {
+        println!("Warning: breeding with the {} engine, so this run can't be repeated from its seed, set genetics.engine = \"seeded\" for that", experiment.engine);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let genetics = match &resume {
+        Some(checkpoint) => checkpoint.genetics.clone(),
+        None => experiment.genetics.clone()
+    };
+    // Tokens per seat in each evaluation match, 3 plays full elimination matches and counts each match lost
+    let tokens: u32 = match &resume {
+        Some(checkpoint) => checkpoint.tokens,
+        None => experiment.tokens
+    };
+    // Fresh models need 28 outputs, 14 card scores then 14 declaration heads
     //let mut autobots = Nod::build_agency_from_root(root.clone(), 25, true);
-    let mut decepticons = Nod::build_agency_from_root(decp_root, 150, false);
+    let mut decepticons = match &resume {
+        Some(checkpoint) => checkpoint.restore(&experiment.checkpoint, experiment.encoder()).expect("Failed to restore checkpoint"),
+        None => Nod::build_agency_from_root(experiment.root().expect("Failed to load the population source"), experiment.size, false)
+    };
+    let mut hall = HallOfFame::read(&experiment.hall).expect("Failed to read hall of fame");
+    // Recorded once the population and the hall are set up, so a run that can't start leaves no config behind
+    if resume.is_none() {
+        std::fs::write(&experiment.config_out, experiment.to_toml()).expect("Failed to record experiment config");
+    }
+    let probes = build_probes(experiment.probes, 99);
+    let generations: usize = experiment.generations;
+    let games: usize = experiment.games;
     let mut results: Vec<Vec<usize>> = vec![];
-    for i in 0..200 {
-        decepticons.genetic_generation(75, 10, 35, 30, 5, 0.005, 0.2);
-        //let autobot_team: Vec<Player> = autobots.networks_iter().map(|f| Player::Nod(BotNod { hand: vec![], losses: 0, brain: f })).collect();
-        let decepticon_team: Vec<Player> = decepticons.networks_iter().map(|f| Player::Nod(BotNod { hand: vec![], losses: 0, brain: f })).collect();
-        /*
-        let mut basic = Player::Bot(BasicBot { hand: vec![], losses: 0, priorities: [0,0,0,0,0,0,0,0,0,0,0,0,0,0]});
-        let mut basic2 = Player::Bot(BasicBot { hand: vec![], losses: 0, priorities: [0,0,0,0,0,0,0,0,0,0,0,0,0,0]});
-        let mut basic3 = Player::Bot(BasicBot { hand: vec![], losses: 0, priorities: [11,12,13,7,6,5,4,3,2,1,0,8,9,10]});
-        let mut basic4 = Player::Bot(BasicBot { hand: vec![], losses: 0, priorities: [11,12,13,7,6,5,4,3,2,1,0,8,9,10]});
-        let mut basic5 = Player::Bot(BasicBot { hand: vec![], losses: 0, priorities: [11,12,13,7,6,5,4,3,2,1,0,8,9,10]});
-        basic.rand();
-        basic2.rand();
-        basic3.rand();
-        basic4.rand();
-        basic5.rand();
-        */
-        let mut basic6 = Player::Bot(BasicBot { hand: vec![], losses: 0, priorities: [11,12,13,7,6,5,4,3,2,1,0,8,9,10]});
+    let mut estimates: Vec<LossEstimate> = vec![];
+    let mut scores: Vec<f64> = vec![];
+    // Training games so far, comparable with the games played printed by nod-99 reinforce
+    let mut games_played: usize = 0;
+    let mut first_generation: usize = 0;
+    if let Some(checkpoint) = &resume {
+        games_played = checkpoint.games_played;
+        first_generation = checkpoint.generation;
+        println!("Resuming {} at generation {}", experiment.checkpoint, first_generation);
+        if first_generation >= generations {
+            println!("{} already finished all {} generations", experiment.checkpoint, generations);
+            return Ok(());
+        }
+    }
+    for i in first_generation..generations {
+        // Each generation draws from its own stream, so with the seeded engine a resumed run plays out exactly as the original would have
+        rng = StdRng::seed_from_u64(job_seed(seed, i));
+        if experiment.engine == "seeded" {
+            let mating: Option<Vec<usize>> = if experiment.speciation {
+                Some(speciate(&behaviour_signatures(decepticons.networks_iter().collect(), experiment.encoder(), &probes, &rules), experiment.species_threshold))
+            } else {
+                None
+            };
+            seeded_generation(&mut decepticons, &genetics, mating.as_ref(), &mut rng);
+        } else {
+            let g = &genetics;
+            decepticons.genetic_generation(g.survivors, g.parents, g.crossovers, g.mutants, g.mutation_passes, g.mutation_rate, g.mutation_size);
+        }
+        let networks: Vec<Network> = decepticons.networks_iter().collect();
+        let signatures = behaviour_signatures(networks.clone(), experiment.encoder(), &probes, &rules);
+        let species = speciate(&signatures, experiment.species_threshold);
+        //let autobot_team: Vec<Box<dyn Player>> = autobots.networks_iter().map(|f| Box::new(BotNod { hand: vec![], losses: 0, brain: f }) as Box<dyn Player>).collect();
+        let decepticon_team: Vec<Box<dyn Player>> = networks.iter().map(|f| Box::new(BotNod { hand: vec![], losses: 0, brain: f.clone(), encoder: experiment.encoder() }) as Box<dyn Player>).collect();
+        let opponents: Vec<Box<dyn Player>> = experiment.opponents(&mut rng).expect("Failed to load opponents");
 
-        results = run_team_round_robin_1v1(vec![basic6], decepticon_team, 150);
+        let num_opponents = opponents.len();
+        let evaluation_start = time::Instant::now();
+        if experiment.racing {
+            let race = RaceParams { elite: genetics.survivors, batch: experiment.racing_batch, max_games: games, z: experiment.confidence_z };
+            let (opponent_losses, raced) = race_team(opponents, decepticon_team, &race, tokens, &rules, &mut rng);
+            estimates = raced;
+            results = vec![opponent_losses, estimates.iter().map(|e| e.losses).collect()];
+            // Rank on the upper bound of the loss rate, so an agent that stopped early is not favoured by luck
+            scores = estimates.iter().map(|e| -e.high).collect();
+        } else if experiment.table_size > 2 {
+            // Two full rotations per table, so every agent starts once from each seat and follows each neighbour both ways
+            let per_table = 2 * experiment.table_size;
+            let rounds = games.div_ceil(per_table).max(1);
+            let tables = run_table_tournament(vec![opponents, decepticon_team], experiment.table_size, rounds, per_table, tokens, &rules, &mut rng);
+            if i % experiment.report_every.max(1) == 0 {
+                let seats: Vec<String> = tables.seat_losses.iter().map(|l| l.to_string()).collect();
+                println!("Losses by starting seat: {}", seats.join(" "));
+            }
+            estimates = tables.losses[1].iter().zip(tables.games[1].iter()).map(|(&l, &g)| LossEstimate::new(l, g, experiment.confidence_z)).collect();
+            scores = estimates.iter().map(|e| -e.rate()).collect();
+            results = tables.losses;
+        } else {
+            results = run_team_round_robin_1v1(opponents, decepticon_team, games, tokens, &rules, &mut rng);
+            estimates = results[1].iter().map(|&l| LossEstimate::new(l, num_opponents * games, experiment.confidence_z)).collect();
+            scores = results[1].iter().map(|&f| -(f as f64)).collect();
+        }
+        let eval_seconds = evaluation_start.elapsed().as_secs_f64();
+        if experiment.speciation {
+            let fitness: Vec<f64> = estimates.iter().map(|e| 1.0 - if experiment.racing { e.high } else { e.rate() }).collect();
+            scores = shared_fitness(&fitness, &species);
+        }
+        games_played += estimates.iter().map(|e| e.games).sum::<usize>();
+        let mut metrics = GenerationMetrics::measure(i, games_played, eval_seconds, &estimates, &scores, &genetics);
+        metrics.weight_diversity = weight_diversity(&networks.iter().map(|n| n.get_weights()).collect::<Vec<Vec<f64>>>());
+        metrics.behaviour_diversity = behaviour_diversity(&signatures);
+        metrics.species = species.iter().max().map_or(0, |s| s + 1);
+        metrics.append(&experiment.metrics).expect("Failed to write metrics");
         //let auto_inversion = results[0].iter().map(|f| 1.0 / f.clone() as f64).collect();
         //autobots.reorder(auto_inversion);
         /*
@@ -474,42 +2894,588 @@ fn main() {
         }
         autobots.genetic_generation(15, 3, 4, 3, 5, 0.01, 0.5);
         */
-        let decp_inversion = results[1].iter().map(|f| -1.0 * f.clone() as f64).collect();
-        decepticons.reorder(decp_inversion);
-        if i == 199 {
+        decepticons.reorder(scores.clone());
+        if i + 1 == generations {
             let mut j = 0;
             for a in decepticons.networks_iter() {
                 if j == 0 {
-                    a.model_to_txt(String::from("decp_7"));
+                    save_model(&a, &experiment.model_out, &experiment.model_header("genetic", vec![("generation", i.to_string()), ("games_played", games_played.to_string())])).expect("Failed to save the champion");
+                    // Log a few games of the champion against the basic bot for auditing with replay
+                    let mut champion = BotNod { hand: vec![], losses: 0, brain: a, encoder: experiment.encoder() };
+                    let mut basic = BasicBot::reference();
+                    let mut log = GameLog::default();
+                    for k in 0..experiment.logged_matches {
+                        let mut players: Vec<&mut dyn Player> = vec![&mut champion, &mut basic];
+                        run_match(&mut players, tokens, k % 2, &rules, &mut rng, Some(&mut log));
+                    }
+                    log.write_jsonl(&experiment.game_log).expect("Failed to write game log");
                     j = 1;
                 }
             }
         }
-        if i % 10 == 0 {
-            let mut top_loss: f64 = 0.0;
-            for j in 0..5 {
-                top_loss -= decepticons.agents[j].score;
-            }
-
-            println!("Generation {}\nDecp top 5 avg loss rate: {}", i, top_loss / results[0].len() as f64 / 5.0 / 150.0);
+        if i % experiment.report_every.max(1) == 0 {
+            let mut ratings = generation_ratings(&estimates);
+            ratings.sort_by(|a, b| b.partial_cmp(a).unwrap());
+            println!("Generation {}, games played: {}\nDecp top 5 avg loss rate: {}", i, games_played, top_loss_rate(&estimates, &scores, 5));
+            println!("Ratings against the opponents: best {:.0}, median {:.0}", ratings[0], ratings[ratings.len() / 2]);
+            println!("Diversity: weight distance {:.4}, behaviour {:.3}, {} species", metrics.weight_diversity, metrics.behaviour_diversity, metrics.species);
             println!("Time Since start: {:?}",time::Instant::now().duration_since(start));
         }
+        if (i + 1) % experiment.hall_every.max(1) == 0 || i + 1 == generations {
+            if let Some(champion) = decepticons.networks_iter().next() {
+                let rating = hall.induct(champion, i, &experiment, tokens, &rules, &mut rng).expect("Failed to rate the champion");
+                hall.write().expect("Failed to write hall of fame");
+                println!("Generation {} champion rated {:.0} against the hall of fame", i, rating);
+            }
+        }
+        if (i + 1) % experiment.checkpoint_every.max(1) == 0 || i + 1 == generations {
+            Checkpoint::capture(&decepticons, i + 1, seed, games_played, &rules, &genetics, tokens).write(&experiment.checkpoint, &decepticons,
+                &experiment.model_header("genetic", vec![("generation", (i + 1).to_string())])).expect("Failed to write checkpoint");
+        }
     }
     
     let mut auto_loss = 0;
     let mut decp_loss = 0;
-    for i in 0..results[0].len() {
-        auto_loss += results[0][i];
+    for loss in &results[0] {
+        auto_loss += loss;
     };
-    for i in 0..results[1].len() {
-        decp_loss += results[1][i];
+    for loss in &results[1] {
+        decp_loss += loss;
     };
-    let mut top_loss: f64 = 0.0;
-    for j in 0..5 {
-        top_loss -= decepticons.agents[j].score;
-    }
-    println!("Decp top 5 avg loss rate: {}", top_loss / 5.0 / results[0].len() as f64 / 150.0);
+    println!("Decp top 5 avg loss rate: {}", top_loss_rate(&estimates, &scores, 5));
     println!("The autobots lost a total of {} games, the decpticons lost a total of {}", auto_loss, decp_loss);
     println!("The total loss vectors\nAutobots:\n{:?}\nDecepticons:\n{:?}", results[0], results[1]);
+    println!("Hall of fame:");
+    hall.report();
+
+    // Yardstick: the champion against the Monte Carlo search bot
+    if experiment.benchmark_simulations > 0 {
+        if let Some(brain) = decepticons.networks_iter().next() {
+            let champion: Box<dyn Player> = Box::new(BotNod { hand: vec![], losses: 0, brain, encoder: experiment.encoder() });
+            let search: Box<dyn Player> = Box::new(SearchBot::new(experiment.benchmark_simulations, rng.gen()));
+            let bench = run_team_round_robin_1v1(vec![search], vec![champion], experiment.benchmark_games, tokens, &rules, &mut rng);
+            println!("Against the search bot the champion lost {} of {}, the search bot lost {}", bench[1][0], experiment.benchmark_games, bench[0][0]);
+        }
+    }
+    // And against the endgame hybrid
+    if let (Some(hybrid), Some(brain)) = (experiment.hybrid(), decepticons.networks_iter().next()) {
+        let champion: Box<dyn Player> = Box::new(BotNod { hand: vec![], losses: 0, brain, encoder: experiment.encoder() });
+        let bench = run_team_round_robin_1v1(vec![hybrid], vec![champion], experiment.benchmark_games, tokens, &rules, &mut rng);
+        println!("Against the hybrid bot the champion lost {} of {}, the hybrid bot lost {}", bench[1][0], experiment.benchmark_games, bench[0][0]);
+    }
     println!("Time Since start: {:?}",time::Instant::now().duration_since(start));
-}
\ No newline at end of file
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn shuffled_deck(seed: u64) -> Vec<Card> {
+        let mut deck = build_deck();
+        deck.shuffle(&mut StdRng::seed_from_u64(seed));
+        deck
+    }
+
+    // The event stream of a few games between two basic bots with seeded priorities
+    fn played_games(seed: u64) -> (Vec<i64>, Vec<String>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut a = BasicBot::default();
+        let mut b = BasicBot::default();
+        a.rand(&mut rng);
+        b.rand(&mut rng);
+        let mut log = GameLog::default();
+        let mut losers: Vec<i64> = Vec::new();
+        for k in 0..20 {
+            let mut players: Vec<&mut dyn Player> = vec![&mut a, &mut b];
+            losers.push(run_game(&mut players, k % 2, &Ruleset::standard(), &mut rng, Some(&mut log)));
+        }
+        (losers, log.events.iter().map(|e| e.to_json()).collect())
+    }
+
+    #[test]
+    fn same_seed_gives_same_deck() {
+        assert!(shuffled_deck(7) == shuffled_deck(7));
+        assert!(shuffled_deck(7) != shuffled_deck(8));
+    }
+
+    #[test]
+    fn same_seed_gives_same_games() {
+        let (losers, events) = played_games(11);
+        assert_eq!(played_games(11), (losers.clone(), events));
+        assert_ne!(played_games(12).1, played_games(11).1);
+    }
+
+    #[test]
+    fn toml_rejects_text_after_a_value() {
+        assert!(parse_toml("[run]\nseed = 5 # drawn once\nrules = \"standard\" # the default\n").is_ok());
+        for text in ["seed = 5 6\n", "rules = \"standard\" extra\n", "shape = [35, 6, 14]]\n"] {
+            assert!(parse_toml(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn metrics_append_to_files_from_before_diversity() {
+        let path = std::env::temp_dir().join(format!("nod99_metrics_{}.csv", std::process::id())).display().to_string();
+        let genetics = GeneticParams { survivors: 15, parents: 3, crossovers: 4, mutants: 3, mutation_passes: 5, mutation_rate: 0.01, mutation_size: 0.5 };
+        let mut metrics = GenerationMetrics::measure(0, 10, 1.0, &[], &[], &genetics);
+        // A file written before the diversity columns existed
+        let old: Vec<(&str, f64)> = metrics.fields().into_iter().filter(|(name, _)| !name.contains("diversity") && *name != "species").collect();
+        let header: Vec<&str> = old.iter().map(|(name, _)| *name).collect();
+        let row: Vec<String> = old.iter().map(|(_, value)| value.to_string()).collect();
+        std::fs::write(&path, format!("{}\n{}\n", header.join(","), row.join(","))).unwrap();
+        metrics.generation = 1;
+        metrics.species = 4;
+        metrics.append(&path).unwrap();
+        let rows = read_metrics(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].generation, 1);
+        assert_eq!(rows[1].genetics.survivors, 15);
+        assert!(rows[1].genetics.mutation_size == 0.5);
+    }
+
+    #[test]
+    fn kept_discards_leave_hands_short() {
+        // Fifteen seats hold more cards than the deck can refill once ten discards stay out
+        let rules = Ruleset::by_name("keep-last-10").unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        let brain = Nod::build_typical_model(vec![35, 6, 14], String::from("tanh"), String::from("sigmoid"));
+        let mut nods: Vec<BotNod> = (0..5).map(|_| BotNod { hand: vec![], losses: 0, brain: brain.clone(), encoder: &ClassicEncoder }).collect();
+        let mut basics: Vec<BasicBot> = (0..10).map(|_| BasicBot::default()).collect();
+        let mut log = GameLog::default();
+        for k in 0..20 {
+            let mut players: Vec<&mut dyn Player> = nods.iter_mut().map(|p| p as &mut dyn Player).collect();
+            players.extend(basics.iter_mut().map(|p| p as &mut dyn Player));
+            let loser = run_game(&mut players, k % 15, &rules, &mut rng, Some(&mut log));
+            assert!((0..15).contains(&loser));
+        }
+        assert!(log.events.iter().any(|e| matches!(e, GameEvent::Play { hand, .. } if hand.len() < 3)));
+    }
+
+    #[test]
+    fn max_seats_keep_every_hand_full() {
+        // Up to max_seats no seat is ever left short, and every game ends on a real bust
+        for (name, seats) in [("standard", 16), ("keep-last-10", 13)] {
+            let rules = Ruleset::by_name(name).unwrap();
+            assert_eq!(rules.max_seats(), seats);
+            let mut rng = StdRng::seed_from_u64(8);
+            let mut basics: Vec<BasicBot> = (0..seats).map(|_| BasicBot::default()).collect();
+            let mut log = GameLog::default();
+            for k in 0..50 {
+                let mut players: Vec<&mut dyn Player> = basics.iter_mut().map(|p| p as &mut dyn Player).collect();
+                run_game(&mut players, (k % seats) as i64, &rules, &mut rng, Some(&mut log));
+            }
+            assert!(log.events.iter().all(|e| !matches!(e, GameEvent::Play { hand, .. } if hand.len() < 3)));
+            assert!(log.events.iter().all(|e| !matches!(e, GameEvent::Bust { total, .. } if *total <= 99)));
+        }
+    }
+
+    #[test]
+    fn every_table_seats_an_opponent() {
+        // One opponent and six agents at tables of four: three tables, each with the opponent in both of its seats
+        let mut rng = StdRng::seed_from_u64(9);
+        let opponents: Vec<Box<dyn Player>> = vec![Box::new(BasicBot::reference())];
+        let agents: Vec<Box<dyn Player>> = (0..6).map(|_| Box::new(BasicBot::default()) as Box<dyn Player>).collect();
+        let tables = run_table_tournament(vec![opponents, agents], 4, 1, 4, 1, &Ruleset::standard(), &mut rng);
+        assert_eq!(tables.games[0], vec![24]);
+        assert_eq!(tables.games[1], vec![4; 6]);
+        assert_eq!(tables.seat_losses.iter().sum::<usize>(), 12);
+        assert_eq!(tables.losses.iter().flatten().sum::<usize>(), 12);
+    }
+
+    #[test]
+    fn endgame_solver_matches_a_hand_count() {
+        // Standard rules at 97 with two seats, the opponent is dealt one card from an unseen Nine, Five and Five.
+        // Two goes to 99 and keeps the OneEye to answer the opponent's Nine, after which the opponent can only hold
+        // a Five and busts, so it never loses. OneEye first loses whenever the opponent holds the Nine, 1/3, and the
+        // King busts. Without the OneEye the Nine beats the Two in the same third of deals.
+        let rules = Ruleset::standard();
+        let history: Vec<Play> = vec![];
+        let full = composition(&build_deck());
+        let solve = |hand: Vec<Card>| {
+            let mut unseen = [0; 14];
+            unseen[Card::Nine.num_form() as usize] = 1;
+            unseen[Card::Five.num_form() as usize] = 2;
+            let held = composition(&hand);
+            let counts: [usize; 14] = std::array::from_fn(|r| full[r] - unseen[r] - held[r]);
+            let state = GameState { total: 97, direction: 1, seat: 0, num_players: 2, counts: &counts, history: &history, deck_size: 3, rules: &rules };
+            let (m, loss) = EndgameSolver::new(&state, 1).solve(&state, &hand).unwrap();
+            (m.play.card.to_string(), loss)
+        };
+        let (card, loss) = solve(vec![Card::King, Card::OneEye, Card::Two]);
+        assert_eq!(card, "Two");
+        assert!(loss.abs() < 1e-12);
+        let (card, loss) = solve(vec![Card::King, Card::Two]);
+        assert_eq!(card, "Two");
+        assert!((loss - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn deck_conserves_cards_across_reshuffles() {
+        // Four hands play and draw through many reshuffles, nothing may appear or vanish under either policy
+        for policy in [ReshufflePolicy::WholeDiscard, ReshufflePolicy::KeepLast(10)] {
+            let mut rng = StdRng::seed_from_u64(6);
+            let mut deck = Deck::new(policy, &mut rng);
+            let mut hands: Vec<Vec<Card>> = vec![vec![]; 4];
+            for _ in 0..3 {
+                for hand in hands.iter_mut() {
+                    hand.extend(deck.draw(&mut rng));
+                }
+            }
+            for turn in 0..500 {
+                let hand = &mut hands[turn % 4];
+                if !hand.is_empty() {
+                    deck.discard(hand.remove(rng.gen_range(0..hand.len())));
+                }
+                hand.extend(deck.draw(&mut rng));
+                assert!(deck.conserved(hands.iter().collect()));
+            }
+            assert!(deck.reshuffles > 10);
+        }
+    }
+
+    #[test]
+    fn kept_discards_are_not_a_reshuffle() {
+        // Ten discards stay out, so the deck only comes back once an eleventh is down
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut deck = Deck::new(ReshufflePolicy::KeepLast(10), &mut rng);
+        let mut held: Vec<Card> = (0..52).filter_map(|_| deck.draw(&mut rng)).collect();
+        for _ in 0..10 {
+            deck.discard(held.pop().unwrap());
+        }
+        assert!(deck.draw(&mut rng).is_none());
+        assert_eq!(deck.reshuffles, 0);
+        deck.discard(held.pop().unwrap());
+        assert!(deck.draw(&mut rng).is_some());
+        assert_eq!((deck.reshuffles, deck.len()), (1, 0));
+    }
+
+    #[test]
+    fn empty_hand_busts_instead_of_playing() {
+        // More seats than cards leaves the last seats with nothing, the first of them to move busts at once
+        let rules = Ruleset::standard();
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut basics: Vec<BasicBot> = (0..53).map(|_| BasicBot::default()).collect();
+        let mut players: Vec<&mut dyn Player> = basics.iter_mut().map(|p| p as &mut dyn Player).collect();
+        let mut log = GameLog::default();
+        assert_eq!(run_game(&mut players, 52, &rules, &mut rng, Some(&mut log)), 52);
+        assert!(players[52].hand().is_empty());
+        assert!(!log.events.iter().any(|e| matches!(e, GameEvent::Play { .. })));
+        assert!(matches!(log.events.last(), Some(GameEvent::Bust { seat: 52, total: 0 })));
+    }
+
+    #[test]
+    fn checkpoint_restores_the_population() {
+        let name = std::env::temp_dir().join(format!("nod99_checkpoint_{}", std::process::id())).display().to_string();
+        let root = Nod::build_typical_model(vec![35, 6, 14], String::from("tanh"), String::from("sigmoid"));
+        let mut agency = Nod::build_agency_from_root(root, 4, false);
+        for (k, agent) in agency.agents.iter_mut().enumerate() {
+            let weights: Vec<f64> = agent.network.get_weights().iter().enumerate().map(|(i, _)| (k * 100 + i) as f64 / 64.0).collect();
+            agent.network.set_weights(weights);
+            agent.score = k as f64 - 1.5;
+        }
+        let genetics = GeneticParams { survivors: 2, parents: 1, crossovers: 1, mutants: 0, mutation_passes: 3, mutation_rate: 0.25, mutation_size: 0.5 };
+        let rules = Ruleset::by_name("keep-last-10").unwrap();
+        let saved = Checkpoint::capture(&agency, 17, u64::MAX - 3, 4321, &rules, &genetics, 3);
+        let header = ModelHeader { encoder: &ClassicEncoder, hidden: String::from("tanh"), output: String::from("sigmoid"), metadata: vec![] };
+        saved.write(&name, &agency, &header).unwrap();
+
+        let loaded = Checkpoint::read(&name);
+        let restored = loaded.as_ref().map_err(|e| e.to_string()).and_then(|c| c.restore(&name, &ClassicEncoder));
+        let _ = std::fs::remove_file(format!("{}_checkpoint.txt", name));
+        let _ = std::fs::remove_file(format!("{}_root.nod", name));
+        let loaded = loaded.unwrap();
+        let restored = restored.unwrap();
+        assert_eq!((loaded.generation, loaded.seed, loaded.games_played, loaded.rules.as_str(), loaded.tokens), (17, u64::MAX - 3, 4321, "keep-last-10", 3));
+        let g = &loaded.genetics;
+        assert_eq!((g.survivors, g.parents, g.crossovers, g.mutants, g.mutation_passes), (2, 1, 1, 0, 3));
+        assert!(g.mutation_rate == 0.25 && g.mutation_size == 0.5);
+        assert_eq!(loaded.scores, saved.scores);
+        assert_eq!(loaded.weights, saved.weights);
+        assert_eq!(restored.agents.iter().map(|a| a.score).collect::<Vec<f64>>(), saved.scores);
+        assert_eq!(restored.agents.iter().map(|a| a.network.get_weights()).collect::<Vec<Vec<f64>>>(), saved.weights);
+    }
+
+    #[test]
+    fn model_file_rejects_an_edit() {
+        let name = std::env::temp_dir().join(format!("nod99_edited_{}", std::process::id())).display().to_string();
+        let brain = Nod::build_typical_model(vec![35, 6, 14], String::from("tanh"), String::from("sigmoid"));
+        let header = ModelHeader { encoder: &ClassicEncoder, hidden: String::from("tanh"), output: String::from("sigmoid"),
+            metadata: vec![(String::from("generation"), String::from("199"))] };
+        save_model(&brain, &name, &header).unwrap();
+        let path = model_path(&name);
+        let saved = read_model_file(&path).map(|(header, _)| header.metadata);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, text.replacen("hidden tanh", "hidden relu", 1)).unwrap();
+        let edited = read_model_file(&path).err();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(saved.unwrap(), header.metadata);
+        assert!(edited.is_some_and(|e| e.contains("checksum")));
+    }
+
+    #[test]
+    fn older_three_file_models_still_load() {
+        // Nod's own three files, with no .nod beside them, and the encoder file written by models of their time
+        let name = std::env::temp_dir().join(format!("nod99_legacy_{}", std::process::id())).display().to_string();
+        let brain = Nod::build_typical_model(vec![35, 6, 14], String::from("tanh"), String::from("sigmoid"));
+        brain.model_to_txt(name.clone());
+        let classic = load_model(&name, "tanh", "sigmoid").map(|(b, h)| (b.get_weights(), h.encoder.tag(), h.hidden));
+        std::fs::write(format!("{}_encoder", name), "busts-v1\n").unwrap();
+        let busts = load_model(&name, "tanh", "sigmoid").map(|(_, h)| h.encoder.tag());
+        for file in ["types", "weights", "connections", "encoder"] {
+            let _ = std::fs::remove_file(format!("{}_{}", name, file));
+        }
+        assert_eq!(classic.unwrap(), (brain.get_weights(), "classic-v1", String::from("tanh")));
+        assert_eq!(busts.unwrap(), "busts-v1");
+    }
+
+    #[test]
+    fn model_refuses_a_different_encoder() {
+        let name = std::env::temp_dir().join(format!("nod99_busts_{}", std::process::id())).display().to_string();
+        let brain = Nod::build_typical_model(vec![BustsEncoder.inputs(), 6, 14], String::from("tanh"), String::from("sigmoid"));
+        let header = ModelHeader { encoder: &BustsEncoder, hidden: String::from("tanh"), output: String::from("sigmoid"), metadata: vec![] };
+        save_model(&brain, &name, &header).unwrap();
+        let wrong = load_model_for(&name, "tanh", "sigmoid", &ClassicEncoder).err();
+        let right = load_model_for(&name, "tanh", "sigmoid", &BustsEncoder).is_ok();
+        let _ = std::fs::remove_file(model_path(&name));
+        let wrong = wrong.expect("a busts-v1 model loaded for classic-v1");
+        assert!(wrong.contains("busts-v1") && wrong.contains("classic-v1"), "{}", wrong);
+        assert!(right);
+    }
+
+    #[test]
+    fn legal_moves_flag_busts_and_forced_replies() {
+        // At 95 with every Eight, Ten and OneEye and three Nines gone, holding Five, Four and Nine:
+        // the Five busts, the Four leaves 99 with nothing unseen that holds it, and after the Nine
+        // the next three cards must all come from the 21 unseen Five and up out of 36 for a bust
+        let rules = Ruleset::standard();
+        let mut counts = [0; 14];
+        counts[Card::Eight.num_form() as usize] = 4;
+        counts[Card::Nine.num_form() as usize] = 3;
+        counts[Card::Ten.num_form() as usize] = 4;
+        counts[Card::OneEye.num_form() as usize] = 2;
+        let history: Vec<Play> = Vec::new();
+        let state = GameState { total: 95, direction: 1, seat: 0, num_players: 2, counts: &counts, history: &history, deck_size: 20, rules: &rules };
+        let moves = state.legal_moves(&[Card::Five, Card::Four, Card::Nine]);
+        assert_eq!(moves.len(), 3);
+        assert_eq!((moves[0].total, moves[0].busts, moves[0].no_safe_reply), (100, true, false));
+        assert_eq!((moves[1].total, moves[1].busts, moves[1].no_safe_reply), (99, false, true));
+        assert_eq!((moves[2].total, moves[2].busts, moves[2].no_safe_reply), (95, false, false));
+        assert!((moves[2].reply_bust_chance - (21.0 * 20.0 * 19.0) / (36.0 * 35.0 * 34.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn match_eliminates_seats_by_tokens() {
+        // Replay the log of a four seat match: each game deals only the seats with tokens left, starts one seat
+        // further round, and the finishing order is the last seat standing then the busted seats latest first
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut basics: Vec<BasicBot> = (0..4).map(|_| BasicBot::default()).collect();
+        for bot in basics.iter_mut() {
+            bot.rand(&mut rng);
+        }
+        let mut log = GameLog::default();
+        let mut players: Vec<&mut dyn Player> = basics.iter_mut().map(|p| p as &mut dyn Player).collect();
+        let order = run_match(&mut players, 3, 2, &Ruleset::standard(), &mut rng, Some(&mut log));
+
+        let mut lives: Vec<u32> = vec![3; 4];
+        let mut eliminated: Vec<usize> = Vec::new();
+        let mut first: usize = 2;
+        let mut alive: Vec<usize> = (0..4).collect();
+        for event in log.events.iter() {
+            match event {
+                GameEvent::Start { players, first: table_first, .. } => {
+                    while lives[first] == 0 {
+                        first = (first + 1) % 4;
+                    }
+                    alive = (0..4).filter(|&s| lives[s] > 0).collect();
+                    assert_eq!(*players, alive.len());
+                    assert_eq!(alive[*table_first], first);
+                    first = (first + 1) % 4;
+                },
+                GameEvent::Bust { seat, .. } => {
+                    let loser = alive[*seat];
+                    lives[loser] -= 1;
+                    if lives[loser] == 0 {
+                        eliminated.push(loser);
+                    }
+                },
+                _ => {}
+            }
+        }
+        assert_eq!(eliminated.len(), 3);
+        eliminated.reverse();
+        let winner: Vec<usize> = (0..4).filter(|&s| lives[s] > 0).collect();
+        assert_eq!(order, [winner.clone(), eliminated].concat());
+        assert_eq!(players.iter().map(|p| p.loss_count()).collect::<Vec<u32>>(), lives.iter().map(|l| 3 - l).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn parallel_round_robin_is_seeded() {
+        let team = |rng: &mut StdRng| -> Vec<Box<dyn Player>> {
+            (0..3).map(|_| {
+                let mut bot: Box<dyn Player> = Box::new(BasicBot::default());
+                bot.rand(rng);
+                bot
+            }).collect()
+        };
+        let results = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (first, second) = (team(&mut rng), team(&mut rng));
+            run_team_round_robin_1v1(first, second, 10, 3, &Ruleset::standard(), &mut rng)
+        };
+        assert_eq!(results(5), results(5));
+    }
+
+    #[test]
+    fn seeded_generation_keeps_survivors_and_repeats() {
+        let params = GeneticParams { survivors: 4, parents: 2, crossovers: 3, mutants: 2, mutation_passes: 2, mutation_rate: 0.5, mutation_size: 0.2 };
+        let bred = |seed: u64| {
+            let root = Nod::build_typical_model(vec![35, 6, 14], String::from("tanh"), String::from("sigmoid"));
+            let mut agency = Nod::build_agency_from_root(root, 10, false);
+            let mut rng = StdRng::seed_from_u64(1);
+            for agent in agency.agents.iter_mut() {
+                let weights: Vec<f64> = agent.network.get_weights().iter().map(|_| rng.gen_range(-1.0..1.0)).collect();
+                agent.network.set_weights(weights);
+            }
+            let before: Vec<Vec<f64>> = agency.networks_iter().map(|n| n.get_weights()).collect();
+            seeded_generation(&mut agency, &params, None, &mut StdRng::seed_from_u64(seed));
+            (before, agency.networks_iter().map(|n| n.get_weights()).collect::<Vec<Vec<f64>>>())
+        };
+        let (before, after) = bred(3);
+        assert_eq!(after.len(), 10);
+        assert_eq!(before[..4], after[..4]);
+        assert_eq!(bred(3).1, after);
+    }
+
+    // Not a test: the reference bot for the protocol tests, which start this test binary again to run it
+    // Quietly the harness only prints its banner before the bot speaks, and connect skips it
+    #[test]
+    #[ignore]
+    fn reference_bot_process() {
+        if std::env::var("NOD99_REFBOT").is_ok() {
+            reference_bot().unwrap();
+        }
+    }
+
+    fn reference_bot_command() -> Vec<String> {
+        std::env::set_var("NOD99_REFBOT", "1");
+        let exe = std::env::current_exe().unwrap().to_string_lossy().to_string();
+        vec![exe, String::from("tests::reference_bot_process"), String::from("--exact"), String::from("--ignored"), String::from("--nocapture"),
+            String::from("--quiet")]
+    }
+
+    fn shell_bot(script: &str) -> Vec<String> {
+        vec![String::from("sh"), String::from("-c"), script.to_string()]
+    }
+
+    // At 95 the Five busts and the Two is the first safe card
+    fn play_at_95(bot: &mut ProcessBot) -> Play {
+        let rules = Ruleset::standard();
+        let counts = [0; 14];
+        let history: Vec<Play> = Vec::new();
+        let state = GameState { total: 95, direction: 1, seat: 0, num_players: 2, counts: &counts, history: &history, deck_size: 40, rules: &rules };
+        bot.hand = vec![Card::Five, Card::Two, Card::King];
+        bot.take_turn(&state)
+    }
+
+    #[test]
+    fn reference_bot_handshake() {
+        let mut bot = ProcessBot::new(reference_bot_command(), time::Duration::from_secs(10));
+        assert_eq!(bot.connect(), Ok(()));
+        assert_eq!(bot.name.as_deref(), Some("refbot"));
+    }
+
+    #[test]
+    fn reference_bot_plays_legal_card() {
+        let mut bot = ProcessBot::new(reference_bot_command(), time::Duration::from_secs(10));
+        let play = play_at_95(&mut bot);
+        assert!(play.card == Card::Two);
+        assert!(bot.hand == vec![Card::Five, Card::King]);
+        assert_eq!(bot.faults(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn illegal_index_is_a_fault_with_fallback() {
+        let mut bot = ProcessBot::new(shell_bot("read l; echo ready bad; while read l; do echo play 7; done"), time::Duration::from_secs(10));
+        let play = play_at_95(&mut bot);
+        assert_eq!(bot.faults(), 1);
+        assert!(play.card == Card::Two);
+        assert!(bot.child.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_restarts_the_bot() {
+        // The first process stalls on its turn, the next one finds the marker and answers
+        let marker = std::env::temp_dir().join(format!("nod99_slow_{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let script = format!("read l; echo ready slow; while read l; do if [ -e '{0}' ]; then echo play 1; else touch '{0}'; sleep 5; fi; done",
+            marker.display());
+        let mut bot = ProcessBot::new(shell_bot(&script), time::Duration::from_millis(500));
+        let play = play_at_95(&mut bot);
+        assert_eq!(bot.faults(), 1);
+        assert!(play.card == Card::Two);
+        assert!(bot.child.is_none());
+        let play = play_at_95(&mut bot);
+        assert_eq!(bot.faults(), 1);
+        assert!(play.card == Card::Two);
+        assert!(bot.child.is_some());
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[test]
+    fn serve_refuses_a_table_it_cannot_deal() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for (humans, bots) in [(1, 0), (0, 1), (0, 17)] {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+            let result = serve(listener, humans, table_bots(bots, None).unwrap(), 1, time::Duration::from_millis(100), &Ruleset::standard(), &mut rng);
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn idle_client_does_not_stall_the_table() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let bots = table_bots(1, None).unwrap();
+            let mut rng = StdRng::seed_from_u64(5);
+            serve(listener, 3, bots, 2, time::Duration::from_millis(100), &Ruleset::standard(), &mut rng)
+        });
+        // The first client answers every prompt, the second never says anything, the third never stops talking
+        let active = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut idle = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut chatty = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut chatter = chatty.try_clone().unwrap();
+        std::thread::spawn(move || {
+            while writeln!(chatter, "nonsense").is_ok() {
+                std::thread::sleep(time::Duration::from_millis(10));
+            }
+        });
+        let mut answers = active.try_clone().unwrap();
+        let mut seen: Vec<String> = Vec::new();
+        for line in io::BufReader::new(active).lines() {
+            let line = line.unwrap();
+            if line.starts_with("Type 1 to") {
+                writeln!(answers, "1").unwrap();
+            } else if line.starts_with("Play ") {
+                let value = line.split(' ').nth(3).unwrap();
+                writeln!(answers, "{}", value).unwrap();
+            }
+            seen.push(line);
+        }
+        server.join().unwrap().unwrap();
+        assert!(seen[0].starts_with("Welcome to 99"));
+        assert_eq!(seen.iter().filter(|l| l.contains(" busts, losses by seat: ")).count(), 2);
+        let mut said = String::new();
+        idle.read_to_string(&mut said).unwrap();
+        assert!(said.contains("Out of time, the server played for you"));
+        let mut said = String::new();
+        chatty.read_to_string(&mut said).unwrap();
+        assert!(said.contains("Out of time, the server played for you"));
+        assert!(!seen.iter().any(|l| l.starts_with("Out of time")));
+    }
+}